@@ -0,0 +1,71 @@
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    path::Path,
+    process,
+};
+
+use assembler::{AsmError, SymbolTable};
+use emulator::{Computer, Debugger};
+
+fn exit_with(errors: Vec<AsmError>) -> ! {
+    for e in errors {
+        eprintln!("{}\n", e);
+    }
+    process::exit(1);
+}
+
+fn main() {
+    // Debug an `.asm` program, or a `.hack` program with the symbols from the `.sym` file next to it that
+    // the assembler writes with --symbols
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let path = args.first().expect("Please supply a .asm or .hack file as the first argument");
+    let source = fs::read_to_string(path).unwrap();
+    let (words, symbols) = if path.ends_with(".asm") {
+        let assembled = assembler::parse(path, &source)
+            .and_then(|program| assembler::resolve(&program))
+            .unwrap_or_else(|errors| exit_with(errors));
+        (assembled.words, assembled.symbols)
+    } else {
+        let words = assembler::parse_hack(path, &source).unwrap_or_else(|errors| exit_with(errors));
+        let sym_path = Path::new(path).with_extension("sym");
+        let symbols = match fs::read_to_string(&sym_path) {
+            Ok(sym) => SymbolTable::from_sym(&sym_path.display().to_string(), &sym)
+                .unwrap_or_else(|errors| exit_with(errors)),
+            Err(_) => SymbolTable::new(),
+        };
+        (words, symbols)
+    };
+
+    let computer = Computer::new(&words).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let mut debugger = Debugger::new(computer, symbols);
+    println!("{} instructions loaded, type help for the commands", words.len());
+    println!("{}", debugger.location());
+    let mut last = String::new();
+    let stdin = io::stdin();
+    loop {
+        print!("(hdb) ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        // Like gdb, an empty line repeats the last command
+        let line = match line.trim() {
+            "" => last.clone(),
+            line => line.to_string(),
+        };
+        if line == "quit" || line == "q" {
+            break;
+        }
+        match debugger.command(&line) {
+            Ok(output) if output.is_empty() => {},
+            Ok(output) => println!("{}", output.trim_end()),
+            Err(e) => println!("error: {}", e),
+        }
+        last = line;
+    }
+}
@@ -0,0 +1,333 @@
+use assembler::{AsmErrorKind, Comp, Dest, Instr, Jump, Reg, Value};
+
+use crate::error::{CpuError, LoadError};
+use crate::profile::Profile;
+use crate::trace::Trace;
+
+pub const ROM_SIZE: usize = 32768;
+pub const RAM_SIZE: usize = 32768;
+// The screen is 512x256 pixels, one bit each, 32 words to a row
+pub const SCREEN: usize = 16384;
+pub const SCREEN_WIDTH: usize = 512;
+pub const SCREEN_HEIGHT: usize = 256;
+pub const KBD: usize = 24576;
+
+// Why `run` returned
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Stop {
+    // The program reached the `(END) @END 0;JMP` loop that Hack programs finish with, or ran off its end
+    Halted,
+    // The program was still going after the given number of cycles
+    CycleLimit,
+}
+
+// The Hack computer: a CPU with A, D and PC registers, reading instructions from ROM and data from RAM,
+// with the screen and keyboard mapped into RAM. Words in ROM are decoded with the assembler's `Instr`, so
+// what runs is exactly what the disassembler would show.
+pub struct Computer {
+    rom: Vec<u16>,
+    // How much of ROM the program takes up
+    len: usize,
+    decoded: Vec<Result<Instr, AsmErrorKind>>,
+    ram: Vec<u16>,
+    a: u16,
+    d: u16,
+    pc: u16,
+    cycles: u64,
+    profile: Option<Profile>,
+    trace: Option<Trace>,
+}
+
+impl Computer {
+    // A computer with the program at the start of ROM and the rest of ROM and all of RAM zeroed
+    pub fn new(program: &[u16]) -> Result<Self, LoadError> {
+        let len = program.len();
+        if len > ROM_SIZE {
+            return Err(LoadError::TooLarge(len));
+        }
+        let mut rom = program.to_vec();
+        rom.resize(ROM_SIZE, 0);
+        let decoded = rom.iter().map(|&word| Instr::from_word(word)).collect();
+        let ram = vec![0; RAM_SIZE];
+        Ok(Computer { rom, len, decoded, ram, a: 0, d: 0, pc: 0, cycles: 0, profile: None, trace: None })
+    }
+
+    // Load the text `.hack` format the assembler writes
+    pub fn from_hack(file: &str, source: &str) -> Result<Self, LoadError> {
+        Computer::new(&assembler::parse_hack(file, source).map_err(LoadError::InvalidHack)?)
+    }
+
+    pub fn a(&self) -> u16 {
+        self.a
+    }
+
+    pub fn d(&self) -> u16 {
+        self.d
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_a(&mut self, value: u16) {
+        self.a = value;
+    }
+
+    pub fn set_d(&mut self, value: u16) {
+        self.d = value;
+    }
+
+    pub fn set_pc(&mut self, value: u16) {
+        self.pc = value;
+    }
+
+    // How many instructions have been executed since the computer was created
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // How much of ROM the program takes up
+    pub fn program_len(&self) -> usize {
+        self.len
+    }
+
+    pub fn rom(&self) -> &[u16] {
+        &self.rom
+    }
+
+    pub fn ram(&self) -> &[u16] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u16] {
+        &mut self.ram
+    }
+
+    // The screen memory map, 32 words to a row with the least significant bit of each word leftmost
+    pub fn screen(&self) -> &[u16] {
+        &self.ram[SCREEN..KBD]
+    }
+
+    // The key code of the key being held down, or 0 for none
+    pub fn set_key(&mut self, key: u16) {
+        self.ram[KBD] = key;
+    }
+
+    // Count the instructions run from now on, and the calls they were run in
+    pub fn start_profile(&mut self) {
+        // LCL is RAM[1]
+        self.profile = Some(Profile::new(self.ram[1]));
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    // Write a row of the CPU test's output for each half of every cycle from now on
+    pub fn start_trace(&mut self, trace: Trace) {
+        self.trace = Some(trace);
+    }
+
+    pub fn trace_mut(&mut self) -> Option<&mut Trace> {
+        self.trace.as_mut()
+    }
+
+    // The instruction that will run next
+    pub fn current(&self) -> Option<&Instr> {
+        self.decoded.get(self.pc as usize).and_then(|instr| instr.as_ref().ok())
+    }
+
+    // Restart the program from ROM[0]. Like the reset bit of the real CPU this leaves memory and the A
+    // and D registers alone.
+    pub fn reset(&mut self) {
+        self.pc = 0;
+    }
+
+    // Execute one instruction
+    pub fn step(&mut self) -> Result<(), CpuError> {
+        let pc = self.pc;
+        let before = (self.a, self.d, self.pc);
+        let in_m = self.ram.get(self.a as usize).copied().unwrap_or(0);
+        let instr = match self.decoded.get(pc as usize) {
+            Some(Ok(instr)) => instr,
+            Some(Err(kind)) => return Err(CpuError::IllegalInstruction { pc, word: self.rom[pc as usize], kind: kind.clone() }),
+            None => return Err(CpuError::PcOutOfRange { pc }),
+        };
+        match instr {
+            Instr::A(Value::Literal(value)) => {
+                self.a = *value as u16;
+                self.pc += 1;
+            },
+            // Decoding a word only ever produces literals
+            Instr::A(value) => unreachable!("decoded A-instruction with value {}", value),
+            Instr::C(dest, comp, jump) => {
+                let address = self.a;
+                let m = if comp.reads_m() { self.read(pc, address)? } else { 0 };
+                let out = alu(comp, self.d, self.a, m);
+                if dest.m {
+                    let word = self.ram.get_mut(address as usize).ok_or(CpuError::AddressOutOfRange { pc, address })?;
+                    *word = out;
+                }
+                if dest.a {
+                    self.a = out;
+                }
+                if dest.d {
+                    self.d = out;
+                }
+                if jumps(jump, out) {
+                    if address as usize >= ROM_SIZE {
+                        return Err(CpuError::JumpOutOfRange { pc, address });
+                    }
+                    self.pc = address;
+                } else {
+                    self.pc += 1;
+                }
+            },
+        }
+        self.cycles += 1;
+        if let Some(profile) = &mut self.profile {
+            profile.record(pc, self.pc, self.ram[1]);
+        }
+        if let (Some(trace), Some(Ok(instr))) = (&mut self.trace, self.decoded.get(pc as usize)) {
+            trace.record(self.cycles - 1, self.rom[pc as usize], instr, in_m, before, (self.a, self.d, self.pc));
+        }
+        Ok(())
+    }
+
+    // Execute instructions until the program halts or `max_cycles` have run
+    pub fn run(&mut self, max_cycles: u64) -> Result<Stop, CpuError> {
+        for _ in 0..max_cycles {
+            if self.halted() {
+                return Ok(Stop::Halted);
+            }
+            self.step()?;
+        }
+        Ok(if self.halted() { Stop::Halted } else { Stop::CycleLimit })
+    }
+
+    // Whether the program is past its last instruction, or in a loop that jumps to itself without changing
+    // anything: either `@X / 0;JMP` at X, or a `0;JMP` that A already points at
+    pub fn halted(&self) -> bool {
+        let pc = self.pc as usize;
+        if pc >= self.len {
+            return true;
+        }
+        let unconditional = |instr: Option<&Result<Instr, AsmErrorKind>>| {
+            matches!(instr, Some(Ok(Instr::C(dest, _, jump))) if *dest == Dest::new() && jump.lt && jump.eq && jump.gt)
+        };
+        match self.decoded.get(pc) {
+            Some(Ok(Instr::A(Value::Literal(target)))) => *target == pc && unconditional(self.decoded.get(pc + 1)),
+            next => unconditional(next) && self.a as usize == pc,
+        }
+    }
+
+    fn read(&self, pc: u16, address: u16) -> Result<u16, CpuError> {
+        self.ram.get(address as usize).copied().ok_or(CpuError::AddressOutOfRange { pc, address })
+    }
+}
+
+// What the ALU outputs for `comp`, with Y being A or M
+pub(crate) fn alu(comp: &Comp, d: u16, a: u16, m: u16) -> u16 {
+    let reg = |r: &Reg| match r {
+        Reg::A => a,
+        Reg::D => d,
+        Reg::M => m,
+    };
+    match comp {
+        Comp::Zero => 0,
+        Comp::One => 1,
+        Comp::MinusOne => 0xFFFF,
+        Comp::Reg(r) => reg(r),
+        Comp::Not(r) => !reg(r),
+        Comp::Neg(r) => reg(r).wrapping_neg(),
+        Comp::Inc(r) => reg(r).wrapping_add(1),
+        Comp::Dec(r) => reg(r).wrapping_sub(1),
+        Comp::Add(y) => d.wrapping_add(reg(y)),
+        Comp::Sub(y) => d.wrapping_sub(reg(y)),
+        Comp::SubFrom(y) => reg(y).wrapping_sub(d),
+        Comp::And(y) => d & reg(y),
+        Comp::Or(y) => d | reg(y),
+    }
+}
+
+// Whether the ALU output, as a two's complement number, satisfies the jump condition
+fn jumps(jump: &Jump, out: u16) -> bool {
+    let out = out as i16;
+    (jump.lt && out < 0) || (jump.eq && out == 0) || (jump.gt && out > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Computer, Stop, KBD, ROM_SIZE, SCREEN};
+    use crate::{CpuError, LoadError};
+
+    fn assembled(source: &str) -> Computer {
+        Computer::new(&assembler::assemble(source).unwrap().words).unwrap()
+    }
+
+    #[test]
+    fn run_max() {
+        let mut computer = Computer::from_hack("Max.hack", include_str!("../../Max.hack")).unwrap();
+        computer.ram_mut()[0] = 3;
+        computer.ram_mut()[1] = 5;
+        assert_eq!(computer.run(1000), Ok(Stop::Halted));
+        assert_eq!(computer.ram()[2], 5);
+        assert_eq!(computer.cycles(), 12);
+
+        computer.reset();
+        computer.ram_mut()[0] = 23456;
+        computer.ram_mut()[1] = 12345;
+        assert_eq!(computer.run(1000), Ok(Stop::Halted));
+        assert_eq!(computer.ram()[2], 23456);
+    }
+
+    #[test]
+    fn run_off_the_end() {
+        let mut computer = Computer::from_hack("Add.hack", include_str!("../../Add.hack")).unwrap();
+        assert_eq!(computer.run(1000), Ok(Stop::Halted));
+        assert_eq!((computer.ram()[0], computer.cycles()), (5, 6));
+    }
+
+    #[test]
+    fn alu_and_jumps() {
+        let mut computer = assembled("@5\nD=-A\n@100\nAM=D-A\nD=D|A\n@0\nD;JLT\n@32767\nD=!A");
+        assert_eq!(computer.run(6), Ok(Stop::CycleLimit));
+        assert_eq!(computer.ram()[100], (-105i16) as u16);
+        assert_eq!(computer.a(), 0);
+        assert_eq!(computer.d(), (-5i16 | -105i16) as u16);
+        computer.step().unwrap();
+        assert_eq!(computer.pc(), 0);
+        computer.set_pc(7);
+        computer.run(2).unwrap();
+        assert_eq!(computer.d(), 0x8000);
+    }
+
+    #[test]
+    fn memory_map() {
+        let mut computer = assembled("@KBD\nD=M\n@SCREEN\nM=D\n(END)\n@END\n0;JMP");
+        computer.set_key(65);
+        assert_eq!(computer.run(100), Ok(Stop::Halted));
+        assert_eq!(computer.screen()[0], 65);
+        assert_eq!(computer.ram()[SCREEN], 65);
+        assert_eq!(computer.ram()[KBD], 65);
+        assert_eq!(computer.cycles(), 4);
+    }
+
+    #[test]
+    fn errors() {
+        let mut computer = Computer::new(&[0b1000_0000_0000_0000]).unwrap();
+        assert!(matches!(computer.step(), Err(CpuError::IllegalInstruction { pc: 0, .. })));
+        let mut computer = assembled("A=-1\nM=0");
+        assert_eq!(computer.run(2), Err(CpuError::AddressOutOfRange { pc: 1, address: 0xFFFF }));
+        let mut computer = assembled("A=-1\n0;JMP");
+        assert_eq!(computer.run(2), Err(CpuError::JumpOutOfRange { pc: 1, address: 0xFFFF }));
+    }
+
+    #[test]
+    fn rom_size() {
+        assert_eq!(Computer::new(&vec![0; ROM_SIZE]).unwrap().program_len(), ROM_SIZE);
+        assert_eq!(Computer::new(&vec![0; ROM_SIZE + 1]).err(), Some(LoadError::TooLarge(ROM_SIZE + 1)));
+        let hack = "0000000000000000\n".repeat(ROM_SIZE + 1);
+        assert_eq!(Computer::from_hack("Big.hack", &hack).err(), Some(LoadError::TooLarge(ROM_SIZE + 1)));
+    }
+}
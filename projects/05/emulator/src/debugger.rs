@@ -0,0 +1,493 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use assembler::{Instr, SymbolKind, SymbolTable, Value};
+
+use crate::cpu::{Computer, RAM_SIZE, ROM_SIZE};
+use crate::error::DebugError;
+
+// Where the VM stack starts, and the pointers into it
+const STACK: usize = 256;
+const SP: usize = 0;
+const LCL: usize = 1;
+const ARG: usize = 2;
+const THIS: usize = 3;
+const THAT: usize = 4;
+// Below the LCL of each VM function are the return address and the caller's LCL, ARG, THIS and THAT
+const FRAME: [&str; 5] = ["return address", "saved LCL", "saved ARG", "saved THIS", "saved THAT"];
+
+// How long `continue` and `next` run before giving control back, in case the program never stops
+const RUN_LIMIT: u64 = 100_000_000;
+
+const HELP: &str = "\
+break [ADDR]      b   stop before running ROM[ADDR], or list breakpoints
+delete [ADDR]     d   remove the breakpoint at ADDR, or all of them
+watch [ADDR]      w   stop when RAM[ADDR] changes, or list watchpoints
+unwatch [ADDR]        remove the watchpoint on RAM[ADDR], or all of them
+step [N]          s   run N instructions, 1 by default
+next              n   run one instruction, running calls through to their return
+continue          c   run until a breakpoint, a watchpoint or the program halts
+registers         r   show A, D, M, PC and the cycle count
+list [ADDR]       l   disassemble ROM around ADDR, or around PC
+x ADDR [N]            show N words of RAM from ADDR, 1 by default
+stack [N]             show the top N words of the VM stack, 16 by default, with its frames
+reset                 restart the program from ROM[0], keeping memory
+help              h   show this
+quit              q   leave the debugger
+
+Addresses are numbers, symbols like LOOP, SP or i, or a symbol plus a number like LOOP+3.
+An empty line repeats the last command.";
+
+// A debugger for a program running on the Hack computer. It knows the program's symbols, so breakpoints
+// can be put on labels and watchpoints on variables, and locations are shown as `LABEL+offset`.
+pub struct Debugger {
+    computer: Computer,
+    symbols: SymbolTable,
+    // Labels ordered by ROM address, for finding the label at or before an address
+    labels: Vec<(usize, String)>,
+    // The names to show for RAM addresses. Where a register has several names, like R0 and SP, the VM
+    // name wins.
+    names: HashMap<usize, String>,
+    breakpoints: BTreeSet<usize>,
+    // Each watched RAM address and the value it had when last checked
+    watchpoints: BTreeMap<usize, u16>,
+}
+
+impl Debugger {
+    pub fn new(computer: Computer, symbols: SymbolTable) -> Self {
+        let mut labels = Vec::new();
+        let mut names: HashMap<usize, String> = HashMap::new();
+        for (name, address, kind) in symbols.sorted() {
+            if kind == SymbolKind::Label {
+                labels.push((address, name.to_string()));
+            } else if names.get(&address).is_none_or(|name| is_register(name)) {
+                names.insert(address, name.to_string());
+            }
+        }
+        labels.sort();
+        Debugger { computer, symbols, labels, names, breakpoints: BTreeSet::new(), watchpoints: BTreeMap::new() }
+    }
+
+    pub fn computer(&self) -> &Computer {
+        &self.computer
+    }
+
+    pub fn computer_mut(&mut self) -> &mut Computer {
+        &mut self.computer
+    }
+
+    // Carry out one command, returning what to show
+    pub fn command(&mut self, line: &str) -> Result<String, DebugError> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok(String::new()),
+        };
+        let args: Vec<&str> = words.collect();
+        let arg = args.first().copied();
+        let count = |idx: usize, default: u64| match args.get(idx) {
+            Some(n) => n.parse::<u64>().map_err(|_| DebugError::InvalidArgument(n.to_string())),
+            None => Ok(default),
+        };
+        match command {
+            "break" | "b" => match arg {
+                Some(arg) => {
+                    let address = self.rom_address(arg)?;
+                    self.breakpoints.insert(address);
+                    Ok(format!("breakpoint at {}", self.describe(address)))
+                },
+                None => Ok(self.breakpoints.iter().map(|address| self.describe(*address) + "\n").collect()),
+            },
+            "delete" | "d" => match arg {
+                Some(arg) => {
+                    let address = self.rom_address(arg)?;
+                    if !self.breakpoints.remove(&address) {
+                        return Err(DebugError::NoBreakpoint(address));
+                    }
+                    Ok(String::new())
+                },
+                None => {
+                    self.breakpoints.clear();
+                    Ok(String::new())
+                },
+            },
+            "watch" | "w" => match arg {
+                Some(arg) => {
+                    let address = self.ram_address(arg)?;
+                    self.watchpoints.insert(address, self.computer.ram()[address]);
+                    Ok(format!("watching {}", self.show_ram(address)))
+                },
+                None => Ok(self.watchpoints.keys().map(|address| self.show_ram(*address) + "\n").collect()),
+            },
+            "unwatch" => match arg {
+                Some(arg) => {
+                    let address = self.ram_address(arg)?;
+                    self.watchpoints.remove(&address).ok_or(DebugError::NoWatchpoint(address))?;
+                    Ok(String::new())
+                },
+                None => {
+                    self.watchpoints.clear();
+                    Ok(String::new())
+                },
+            },
+            "step" | "s" => self.run(count(0, 1)?, None),
+            "next" | "n" => self.next(),
+            "continue" | "c" => self.run(RUN_LIMIT, None),
+            "registers" | "r" => Ok(self.registers()),
+            "list" | "l" => {
+                let address = match arg {
+                    Some(arg) => self.rom_address(arg)?,
+                    None => self.computer.pc() as usize,
+                };
+                Ok(self.list(address))
+            },
+            "x" => {
+                let address = self.ram_address(arg.ok_or(DebugError::MissingArgument("a RAM address"))?)?;
+                let end = (address as u64 + count(1, 1)?).min(RAM_SIZE as u64) as usize;
+                Ok((address..end).map(|address| self.show_ram(address) + "\n").collect())
+            },
+            "stack" => Ok(self.stack(count(0, 16)? as usize)),
+            "reset" => {
+                self.computer.reset();
+                Ok(self.location())
+            },
+            "help" | "h" => Ok(HELP.to_string()),
+            _ => Err(DebugError::UnknownCommand(command.to_string())),
+        }
+    }
+
+    // The instruction about to run, like `ROM[12] LOOP+2: D;JGT`
+    pub fn location(&self) -> String {
+        let pc = self.computer.pc() as usize;
+        format!("{}: {}", self.describe(pc), self.disassemble(pc))
+    }
+
+    // Run up to `max_cycles` instructions, stopping early when the program halts, reaches a breakpoint
+    // other than the one it starts at, or changes a watched address. With `until`, also stop at that
+    // address once SP is no higher than the given value.
+    fn run(&mut self, max_cycles: u64, until: Option<(usize, u16)>) -> Result<String, DebugError> {
+        let mut report = String::new();
+        let mut ran = 0;
+        while ran < max_cycles {
+            let pc = self.computer.pc() as usize;
+            if self.computer.halted() {
+                report += "the program has halted\n";
+                break;
+            }
+            if ran > 0 && self.breakpoints.contains(&pc) {
+                report += "breakpoint\n";
+                break;
+            }
+            if ran > 0 && until.is_some_and(|(address, sp)| pc == address && self.computer.ram()[SP] <= sp) {
+                break;
+            }
+            self.computer.step().map_err(DebugError::Cpu)?;
+            ran += 1;
+            let changes = self.watch_changes();
+            if !changes.is_empty() {
+                report += &changes;
+                break;
+            }
+        }
+        if ran == max_cycles && max_cycles == RUN_LIMIT {
+            report += &format!("still running after {} cycles\n", RUN_LIMIT);
+        }
+        Ok(report + &self.location())
+    }
+
+    // Step one instruction, unless it is a call: an unconditional jump that passes on the address after it
+    // to return to. That is either in D, for code that calls through a routine which saves it (like
+    // `@RET / D=A / @F / 0;JMP`, or the shared call code some VM translators emit), or five words below SP,
+    // where a VM call that builds the frame itself pushes it. A call runs until the return address is
+    // reached with the stack popped back to where it was, leaving at most a return value. Other jumps, like
+    // a loop's back edge or a VM `return`, are just stepped.
+    fn next(&mut self) -> Result<String, DebugError> {
+        let pc = self.computer.pc() as usize;
+        let ram = self.computer.ram();
+        let sp = ram[SP];
+        let instr = self.computer.rom().get(pc).map(|word| Instr::from_word(*word));
+        let jumps = matches!(instr, Some(Ok(Instr::C(_, _, jump))) if jump.lt && jump.eq && jump.gt);
+        let ret = pc as u16 + 1;
+        let pushed = (sp as usize).checked_sub(FRAME.len()).is_some_and(|frame| ram[frame] == ret && ram[LCL] == sp);
+        if !(jumps && (self.computer.d() == ret || pushed)) {
+            return self.run(1, None);
+        }
+        self.run(RUN_LIMIT, Some((pc + 1, sp.wrapping_add(1))))
+    }
+
+    // A line for each watched address that has changed since it was last checked
+    fn watch_changes(&mut self) -> String {
+        let ram = self.computer.ram();
+        let mut changes = String::new();
+        for (address, old) in self.watchpoints.iter_mut() {
+            let new = ram[*address];
+            if new != *old {
+                let name = self.names.get(address).map_or(String::new(), |name| format!(" {}", name));
+                changes += &format!("RAM[{}]{}: {} -> {}\n", address, name, *old as i16, new as i16);
+                *old = new;
+            }
+        }
+        changes
+    }
+
+    fn registers(&self) -> String {
+        let computer = &self.computer;
+        let m = match computer.ram().get(computer.a() as usize) {
+            Some(m) => format!("{}", *m as i16),
+            None => "-".to_string(),
+        };
+        format!(
+            "A={} D={} M={} PC={} cycles={}\n{}",
+            computer.a() as i16,
+            computer.d() as i16,
+            m,
+            computer.pc(),
+            computer.cycles(),
+            self.location()
+        )
+    }
+
+    // The instructions around `address`, with their labels. `=>` marks the PC and `*` a breakpoint.
+    fn list(&self, address: usize) -> String {
+        let start = address.saturating_sub(5);
+        let end = (address + 6).min(self.computer.program_len().max(start + 1));
+        let mut out = String::new();
+        for addr in start..end {
+            let first_label = self.labels.partition_point(|(label, _)| *label < addr);
+            for (_, label) in self.labels[first_label..].iter().take_while(|(label, _)| *label == addr) {
+                out += &format!("({})\n", label);
+            }
+            let pc = if addr == self.computer.pc() as usize { "=>" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&addr) { "*" } else { " " };
+            out += &format!("{}{}{:>5}    {}\n", breakpoint, pc, addr, self.disassemble(addr));
+        }
+        out
+    }
+
+    // The words on the VM stack from the top down, marking where LCL and ARG point and, following the
+    // saved LCLs back through the callers, the frame below each function's locals
+    fn stack(&self, count: usize) -> String {
+        let ram = self.computer.ram();
+        let sp = ram[SP] as usize;
+        let mut out = format!(
+            "SP={} LCL={} ARG={} THIS={} THAT={}\n",
+            sp, ram[LCL], ram[ARG], ram[THIS], ram[THAT]
+        );
+        if !(STACK..RAM_SIZE).contains(&sp) {
+            return out + "SP doesn't point into the stack\n";
+        }
+        let mut notes: HashMap<usize, Vec<String>> = HashMap::new();
+        notes.entry(ram[LCL] as usize).or_default().push("<- LCL".to_string());
+        notes.entry(ram[ARG] as usize).or_default().push("<- ARG".to_string());
+        let mut lcl = ram[LCL] as usize;
+        while lcl >= STACK + FRAME.len() && lcl <= sp {
+            for (idx, what) in FRAME.iter().enumerate() {
+                let address = lcl - FRAME.len() + idx;
+                let note = match idx {
+                    0 => format!("{} {}", what, self.describe(ram[address] as usize)),
+                    _ => what.to_string(),
+                };
+                notes.entry(address).or_default().push(note);
+            }
+            // Each caller's frame is further down the stack, so this always ends
+            let caller = ram[lcl - FRAME.len() + 1] as usize;
+            if caller >= lcl {
+                break;
+            }
+            lcl = caller;
+        }
+        for address in (STACK.max(sp.saturating_sub(count))..sp).rev() {
+            let note = notes.get(&address).map_or(String::new(), |notes| notes.join(", "));
+            out += format!("{:<20}{}", format!("RAM[{}] = {}", address, ram[address] as i16), note).trim_end();
+            out += "\n";
+        }
+        out
+    }
+
+    // A ROM address as `ROM[12] LOOP+2`
+    fn describe(&self, address: usize) -> String {
+        let idx = self.labels.partition_point(|(label, _)| *label <= address);
+        match idx.checked_sub(1).map(|idx| &self.labels[idx]) {
+            Some((label, name)) if *label == address => format!("ROM[{}] {}", address, name),
+            Some((label, name)) => format!("ROM[{}] {}+{}", address, name, address - label),
+            None => format!("ROM[{}]", address),
+        }
+    }
+
+    // The instruction at `address`. A-instructions loading the target of the jump after them show the
+    // label, and those loading a variable or data block show its name.
+    fn disassemble(&self, address: usize) -> String {
+        let rom = self.computer.rom();
+        let instr = match rom.get(address).map(|word| Instr::from_word(*word)) {
+            Some(Ok(instr)) => instr,
+            Some(Err(e)) => return format!("illegal instruction {:016b}: {}", rom[address], e),
+            None => return "outside of ROM".to_string(),
+        };
+        let value = match instr {
+            Instr::A(Value::Literal(value)) => value,
+            instr => return instr.to_string(),
+        };
+        let next = rom.get(address + 1).map(|word| Instr::from_word(*word));
+        let jumps = matches!(next, Some(Ok(Instr::C(_, _, jump))) if jump.is_jump());
+        let label = self.labels.iter().find(|(label, _)| *label == value).map(|(_, name)| name);
+        let variable = self.names.get(&value).filter(|name| {
+            matches!(self.symbols.kind(name), Some(SymbolKind::Variable | SymbolKind::Data))
+        });
+        match (label, variable) {
+            (Some(label), _) if jumps => format!("@{}", label),
+            (_, Some(variable)) => format!("@{}", variable),
+            _ => format!("@{}", value),
+        }
+    }
+
+    fn show_ram(&self, address: usize) -> String {
+        let name = self.names.get(&address).map_or(String::new(), |name| format!(" {}", name));
+        format!("RAM[{}]{} = {}", address, name, self.computer.ram()[address] as i16)
+    }
+
+    // A number, a symbol, or a symbol plus a number
+    fn address(&self, arg: &str) -> Result<(usize, Option<SymbolKind>), DebugError> {
+        let (base, offset) = match arg.split_once('+') {
+            Some((base, offset)) => {
+                (base, offset.parse::<usize>().map_err(|_| DebugError::InvalidArgument(arg.to_string()))?)
+            },
+            None => (arg, 0),
+        };
+        if let Ok(address) = base.parse::<usize>() {
+            return Ok((address + offset, None));
+        }
+        let address = self.symbols.get(base).ok_or_else(|| DebugError::UnknownSymbol(base.to_string()))?;
+        Ok((address + offset, self.symbols.kind(base)))
+    }
+
+    fn rom_address(&self, arg: &str) -> Result<usize, DebugError> {
+        match self.address(arg)? {
+            (_, Some(kind)) if kind != SymbolKind::Label => Err(DebugError::WrongKind(arg.to_string(), kind)),
+            (address, _) if address >= ROM_SIZE => Err(DebugError::AddressOutOfRange(address)),
+            (address, _) => Ok(address),
+        }
+    }
+
+    fn ram_address(&self, arg: &str) -> Result<usize, DebugError> {
+        match self.address(arg)? {
+            (_, Some(SymbolKind::Label)) => Err(DebugError::WrongKind(arg.to_string(), SymbolKind::Label)),
+            (address, _) if address >= RAM_SIZE => Err(DebugError::AddressOutOfRange(address)),
+            (address, _) => Ok(address),
+        }
+    }
+}
+
+// R0 to R15, which are better known by other names where they have them
+fn is_register(name: &str) -> bool {
+    name.strip_prefix('R').is_some_and(|n| n.parse::<u8>().is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use assembler::SymbolKind;
+
+    use super::Debugger;
+    use crate::{Computer, DebugError};
+
+    fn debugger(source: &str) -> Debugger {
+        let assembled = assembler::assemble(source).unwrap();
+        Debugger::new(Computer::new(&assembled.words).unwrap(), assembled.symbols)
+    }
+
+    #[test]
+    fn breakpoints_and_watchpoints() {
+        let mut debugger = debugger("@10\nD=A\n@i\nM=D\n(LOOP)\n@i\nMD=M-1\n@LOOP\nD;JGT\n(END)\n@END\n0;JMP");
+        assert_eq!(debugger.command("break LOOP+2").unwrap(), "breakpoint at ROM[6] LOOP+2");
+        assert_eq!(debugger.command("watch i").unwrap(), "watching RAM[16] i = 0");
+        assert_eq!(debugger.command("c").unwrap(), "RAM[16] i: 0 -> 10\nROM[4] LOOP: @i");
+        assert_eq!(debugger.command("c").unwrap(), "RAM[16] i: 10 -> 9\nROM[6] LOOP+2: @LOOP");
+        assert_eq!(debugger.command("unwatch i").unwrap(), "");
+        assert_eq!(debugger.command("c").unwrap(), "breakpoint\nROM[6] LOOP+2: @LOOP");
+        assert_eq!(debugger.command("x i 2").unwrap(), "RAM[16] i = 8\nRAM[17] = 0\n");
+        assert_eq!(debugger.command("d LOOP+2").unwrap(), "");
+        assert_eq!(debugger.command("continue").unwrap(), "the program has halted\nROM[8] END: @END");
+        assert_eq!(debugger.command("r").unwrap(), "A=4 D=0 M=0 PC=8 cycles=44\nROM[8] END: @END");
+
+        assert_eq!(debugger.command("break i"), Err(DebugError::WrongKind("i".to_string(), SymbolKind::Variable)));
+        assert_eq!(debugger.command("watch END"), Err(DebugError::WrongKind("END".to_string(), SymbolKind::Label)));
+        assert_eq!(debugger.command("x FOO"), Err(DebugError::UnknownSymbol("FOO".to_string())));
+        assert_eq!(debugger.command("delete 3"), Err(DebugError::NoBreakpoint(3)));
+        assert_eq!(debugger.command("jump"), Err(DebugError::UnknownCommand("jump".to_string())));
+    }
+
+    #[test]
+    fn step_and_list() {
+        let mut debugger = debugger("@10\nD=A\n@i\nM=D\n(LOOP)\n@i\nMD=M-1\n@LOOP\nD;JGT\n(END)\n@END\n0;JMP");
+        assert_eq!(debugger.command("step 3").unwrap(), "ROM[3]: M=D");
+        debugger.command("b LOOP").unwrap();
+        let list = [
+            "       0    @10",
+            "       1    D=A",
+            "       2    @i",
+            " =>    3    M=D",
+            "(LOOP)",
+            "*      4    @i",
+            "       5    MD=M-1",
+            "       6    @LOOP",
+            "       7    D;JGT",
+            "(END)",
+            "       8    @END",
+        ];
+        assert_eq!(debugger.command("list").unwrap(), list.join("\n") + "\n");
+        assert_eq!(debugger.command("n").unwrap(), "ROM[4] LOOP: @i");
+    }
+
+    #[test]
+    fn next_steps_over_plain_jumps() {
+        // The back edge of a loop and the jump into the halting loop aren't calls
+        let mut debugger = debugger("@3\nD=A\n(LOOP)\nD=D-1\n@DONE\nD;JEQ\n@LOOP\n0;JMP\n(DONE)\n@END\n0;JMP\n(END)\n@END\n0;JMP");
+        debugger.command("step 6").unwrap();
+        assert_eq!(debugger.location(), "ROM[6] LOOP+4: 0;JMP");
+        assert_eq!(debugger.command("next").unwrap(), "ROM[2] LOOP: D=D-1");
+        debugger.command("b DONE+1").unwrap();
+        debugger.command("c").unwrap();
+        assert_eq!(debugger.command("next").unwrap(), "ROM[9] END: @END");
+        assert_eq!(debugger.computer().cycles(), 17);
+    }
+
+    #[test]
+    fn next_runs_calls() {
+        // F returns to the address in RAM[256], popping the stack back below it
+        let source = "@RET\nD=A\n@F\n0;JMP\n(RET)\n@END\n0;JMP\n(F)\n@250\nD=A\n@SP\nM=D\n@256\nA=M\n0;JMP\n\
+                      (END)\n@END\n0;JMP";
+        let call = |d: u16, lcl: u16| {
+            let mut debugger = debugger(source);
+            let ram = debugger.computer_mut().ram_mut();
+            (ram[0], ram[1], ram[256]) = (261, lcl, 4);
+            debugger.command("step 3").unwrap();
+            debugger.computer_mut().set_d(d);
+            debugger.command("next").unwrap()
+        };
+        // The return address is passed in D, or pushed in a VM frame that LCL points past
+        assert_eq!(call(4, 0), "ROM[4] RET: @END");
+        assert_eq!(call(0, 261), "ROM[4] RET: @END");
+        // Without either it's just a jump
+        assert_eq!(call(0, 0), "ROM[6] F: @250");
+    }
+
+    #[test]
+    fn vm_stack() {
+        // Stop in PongGame.new, which is called from PongGame.newInstance with no arguments, and step over its call
+        // to Memory.alloc
+        let assembled = assembler::assemble(include_str!("../../../06/pong/Pong.asm")).unwrap();
+        let mut debugger = Debugger::new(Computer::new(&assembled.words).unwrap(), assembled.symbols);
+        debugger.command("break ponggame.new").unwrap();
+        assert_eq!(debugger.command("c").unwrap(), "breakpoint\nROM[3930] ponggame.new: @7");
+        let stack = "SP=277 LCL=277 ARG=272 THIS=0 THAT=0\n\
+                     RAM[276] = 0        saved THAT\n\
+                     RAM[275] = 0        saved THIS\n\
+                     RAM[274] = 267      saved ARG\n\
+                     RAM[273] = 272      saved LCL\n\
+                     RAM[272] = 4520     <- ARG, return address ROM[4520] RET_ADDRESS_CALL73\n\
+                     RAM[271] = 0        saved THAT\n";
+        assert_eq!(debugger.command("stack 6").unwrap(), stack);
+        debugger.command("b ponggame.new+17").unwrap();
+        assert_eq!(debugger.command("c").unwrap(), "breakpoint\nROM[3947] ponggame.new+17: 0;JMP");
+        assert_eq!(debugger.command("next").unwrap(), "ROM[3948] RET_ADDRESS_CALL53: @0");
+        assert_eq!(debugger.command("x SP").unwrap(), "RAM[0] SP = 278\n");
+    }
+}
@@ -0,0 +1,158 @@
+use std::{fmt, io};
+
+use assembler::{AsmError, AsmErrorKind, SymbolKind};
+
+use crate::cpu::ROM_SIZE;
+
+// Why a program couldn't be put in ROM
+#[derive(Debug, PartialEq, Clone)]
+pub enum LoadError {
+    // The diagnostics from reading a `.hack` file
+    InvalidHack(Vec<AsmError>),
+    // The program is this many words long, more than ROM holds
+    TooLarge(usize),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::InvalidHack(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", errors.join("\n\n"))
+            },
+            LoadError::TooLarge(len) => write!(f, "error: program is {} words long but ROM holds {}", len, ROM_SIZE),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+// Why the computer couldn't execute the instruction at `pc`
+#[derive(Debug, PartialEq, Clone)]
+pub enum CpuError {
+    // The word in ROM doesn't decode to an instruction
+    IllegalInstruction { pc: u16, word: u16, kind: AsmErrorKind },
+    // M was read or written with A outside of RAM
+    AddressOutOfRange { pc: u16, address: u16 },
+    // A jump to an address outside of ROM
+    JumpOutOfRange { pc: u16, address: u16 },
+    // The program ran off the end of ROM
+    PcOutOfRange { pc: u16 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::IllegalInstruction { pc, word, kind } => {
+                write!(f, "ROM[{}]: illegal instruction {:016b}: {}", pc, word, kind)
+            },
+            CpuError::AddressOutOfRange { pc, address } => {
+                write!(f, "ROM[{}]: M accessed at {}, outside of RAM", pc, address)
+            },
+            CpuError::JumpOutOfRange { pc, address } => write!(f, "ROM[{}]: jump to {}, outside of ROM", pc, address),
+            CpuError::PcOutOfRange { pc } => write!(f, "ROM[{}]: program counter is outside of ROM", pc),
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+// Why a debugger command couldn't be carried out
+#[derive(Debug, PartialEq, Clone)]
+pub enum DebugError {
+    UnknownCommand(String),
+    // The command needs an argument, described by the string
+    MissingArgument(&'static str),
+    InvalidArgument(String),
+    UnknownSymbol(String),
+    // A breakpoint was given a symbol that isn't a label, or a watchpoint was given a label
+    WrongKind(String, SymbolKind),
+    AddressOutOfRange(usize),
+    NoBreakpoint(usize),
+    NoWatchpoint(usize),
+    Cpu(CpuError),
+}
+
+impl fmt::Display for DebugError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DebugError::UnknownCommand(c) => write!(f, "unknown command {:?}, try help", c),
+            DebugError::MissingArgument(what) => write!(f, "expected {}", what),
+            DebugError::InvalidArgument(a) => write!(f, "invalid argument {:?}", a),
+            DebugError::UnknownSymbol(s) => write!(f, "no symbol {:?}", s),
+            DebugError::WrongKind(s, SymbolKind::Label) => write!(f, "{:?} is a label, not a RAM address", s),
+            DebugError::WrongKind(s, kind) => write!(f, "{:?} is a {} symbol, not a label", s, kind),
+            DebugError::AddressOutOfRange(address) => write!(f, "address {} is out of range", address),
+            DebugError::NoBreakpoint(address) => write!(f, "no breakpoint at ROM[{}]", address),
+            DebugError::NoWatchpoint(address) => write!(f, "no watchpoint on RAM[{}]", address),
+            DebugError::Cpu(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for DebugError {}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ScriptErrorKind {
+    UnexpectedToken(String),
+    UnexpectedEnd,
+    UnknownCommand(String),
+    InvalidNumber(String),
+    InvalidFormat(String),
+    UnknownVariable(String),
+    UnknownKey(String),
+    // A key event at this cycle comes after one at a later cycle
+    KeyEventOrder(u64),
+    Unsupported(String),
+    NoProgram,
+    FileFailed(String, io::ErrorKind),
+    // The rendered diagnostics from assembling a loaded program
+    AssemblyFailed(String),
+    Cpu(CpuError),
+}
+
+impl fmt::Display for ScriptErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptErrorKind::UnexpectedToken(t) => write!(f, "unexpected {:?}", t),
+            ScriptErrorKind::UnexpectedEnd => write!(f, "script ends in the middle of a command"),
+            ScriptErrorKind::UnknownCommand(c) => write!(f, "unknown command {:?}", c),
+            ScriptErrorKind::InvalidNumber(n) => write!(f, "invalid number {:?}", n),
+            ScriptErrorKind::InvalidFormat(c) => write!(f, "invalid output column {:?}, expected name%D1.6.1", c),
+            ScriptErrorKind::UnknownVariable(v) => write!(f, "unknown variable {:?}", v),
+            ScriptErrorKind::UnknownKey(k) => write!(f, "unknown key {:?}, expected a character or a name like left", k),
+            ScriptErrorKind::KeyEventOrder(cycle) => {
+                write!(f, "the key event at cycle {} comes after one at a later cycle", cycle)
+            },
+            ScriptErrorKind::Unsupported(what) => {
+                write!(f, "can't run {:?}: only Computer.hdl and .asm or .hack programs can be loaded", what)
+            },
+            ScriptErrorKind::NoProgram => write!(f, "no program has been loaded"),
+            ScriptErrorKind::FileFailed(file, e) => write!(f, "could not read {:?}: {}", file, e),
+            ScriptErrorKind::AssemblyFailed(errors) => write!(f, "could not assemble the program\n\n{}", errors),
+            ScriptErrorKind::Cpu(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// A problem with a test script, at the line of the command that caused it
+#[derive(Debug, PartialEq, Clone)]
+pub struct ScriptError {
+    pub kind: ScriptErrorKind,
+    pub file: String,
+    pub line: usize,
+}
+
+impl ScriptError {
+    pub fn new(file: &str, line: usize, kind: ScriptErrorKind) -> Self {
+        ScriptError { kind, file: file.to_string(), line }
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: error: {}", self.file, self.line, self.kind)
+    }
+}
+
+impl std::error::Error for ScriptError {}
@@ -0,0 +1,166 @@
+use crate::{
+    cpu::{Computer, Stop},
+    error::{CpuError, ScriptError, ScriptErrorKind},
+};
+
+// The key codes of the Hack keyboard that aren't printable characters
+const KEY_NAMES: [(&str, u16); 14] = [
+    ("space", 32),
+    ("newline", 128),
+    ("backspace", 129),
+    ("left", 130),
+    ("up", 131),
+    ("right", 132),
+    ("down", 133),
+    ("home", 134),
+    ("end", 135),
+    ("pageup", 136),
+    ("pagedown", 137),
+    ("insert", 138),
+    ("delete", 139),
+    ("esc", 140),
+];
+
+// From `cycle` on, the keyboard register holds `key`, with 0 meaning no key is pressed
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct KeyEvent {
+    pub cycle: u64,
+    pub key: u16,
+}
+
+// Timed key presses and releases to feed to a running program, so interactive programs run the same way
+// every time
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct KeyScript {
+    events: Vec<KeyEvent>,
+}
+
+impl KeyScript {
+    // One event per line, in the order they happen, with `//` comments:
+    //
+    //     120000 press 'q'
+    //     150000 release
+    //     200000 press left
+    //
+    // A key is a character, optionally quoted, or the name of a special key: space, newline, backspace,
+    // left, up, right, down, home, end, pageup, pagedown, insert, delete, esc or f1 to f12.
+    pub fn from_string(file: &str, source: &str) -> Result<Self, ScriptError> {
+        let mut events: Vec<KeyEvent> = Vec::new();
+        for (idx, line) in source.lines().enumerate() {
+            let error = |kind| ScriptError::new(file, idx + 1, kind);
+            let line = line.split("//").next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let cycle = match words.next() {
+                Some(cycle) => cycle.parse().map_err(|_| error(ScriptErrorKind::InvalidNumber(cycle.to_string())))?,
+                None => continue,
+            };
+            let key = match words.next() {
+                Some("press") => {
+                    let key = words.next().ok_or_else(|| error(ScriptErrorKind::UnexpectedEnd))?;
+                    parse_key(key).ok_or_else(|| error(ScriptErrorKind::UnknownKey(key.to_string())))?
+                },
+                Some("release") => 0,
+                Some(word) => return Err(error(ScriptErrorKind::UnknownCommand(word.to_string()))),
+                None => return Err(error(ScriptErrorKind::UnexpectedEnd)),
+            };
+            if let Some(word) = words.next() {
+                return Err(error(ScriptErrorKind::UnexpectedToken(word.to_string())));
+            }
+            if events.last().is_some_and(|last| last.cycle > cycle) {
+                return Err(error(ScriptErrorKind::KeyEventOrder(cycle)));
+            }
+            events.push(KeyEvent { cycle, key });
+        }
+        Ok(KeyScript { events })
+    }
+
+    pub fn events(&self) -> &[KeyEvent] {
+        &self.events
+    }
+
+    // The key held down when `cycle` instructions have run
+    pub fn key_at(&self, cycle: u64) -> u16 {
+        self.events.iter().take_while(|event| event.cycle <= cycle).last().map_or(0, |event| event.key)
+    }
+
+    // Like `Computer::run`, but with the keyboard register following the script
+    pub fn run(&self, computer: &mut Computer, max_cycles: u64) -> Result<Stop, CpuError> {
+        let end = computer.cycles() + max_cycles;
+        loop {
+            let now = computer.cycles();
+            computer.set_key(self.key_at(now));
+            let next = self.events.iter().map(|event| event.cycle).find(|cycle| *cycle > now).unwrap_or(end);
+            match computer.run(next.min(end) - now)? {
+                Stop::CycleLimit if computer.cycles() < end => continue,
+                stop => return Ok(stop),
+            }
+        }
+    }
+}
+
+fn parse_key(input: &str) -> Option<u16> {
+    let unquoted = input.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')).filter(|s| !s.is_empty());
+    let mut chars = unquoted.unwrap_or(input).chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_graphic() => return Some(c as u16),
+        _ => {},
+    }
+    if let Some(n) = input.strip_prefix('f').and_then(|n| n.parse::<u16>().ok()).filter(|n| (1..=12).contains(n)) {
+        return Some(140 + n);
+    }
+    KEY_NAMES.iter().find(|(name, _)| *name == input).map(|(_, key)| *key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyEvent, KeyScript};
+    use crate::{error::ScriptErrorKind, pixel, Computer, Stop};
+
+    #[test]
+    fn parse_events() {
+        let source = "// Quit\n100 press 'q'\n\n200 release // let go\n300 press left\n400 press f12\n400 press x\n";
+        let keys = KeyScript::from_string("k.txt", source).unwrap();
+        let event = |cycle, key| KeyEvent { cycle, key };
+        assert_eq!(keys.events(), [event(100, 113), event(200, 0), event(300, 130), event(400, 152), event(400, 120)]);
+        assert_eq!((keys.key_at(99), keys.key_at(100), keys.key_at(250), keys.key_at(400)), (0, 113, 0, 120));
+
+        let error = |source| KeyScript::from_string("k.txt", source).unwrap_err();
+        assert_eq!(error("10 press\n").kind, ScriptErrorKind::UnexpectedEnd);
+        assert_eq!(error("10 press shift\n").kind, ScriptErrorKind::UnknownKey("shift".to_string()));
+        assert_eq!(error("10 push a\n").kind, ScriptErrorKind::UnknownCommand("push".to_string()));
+        assert_eq!(error("10 release a\n").kind, ScriptErrorKind::UnexpectedToken("a".to_string()));
+        assert_eq!(error("ten press a\n").kind, ScriptErrorKind::InvalidNumber("ten".to_string()));
+        assert_eq!(error("20 press a\n10 release").kind, ScriptErrorKind::KeyEventOrder(10));
+        assert_eq!(error("20 press a\n10 release").line, 2);
+    }
+
+    #[test]
+    fn type_keys() {
+        // Store each key pressed at RAM[R1], then wait for it to be released
+        let source = "(WAIT)\n@KBD\nD=M\n@WAIT\nD;JEQ\n@R1\nA=M\nM=D\n@R1\nM=M+1\n\
+                      (UP)\n@KBD\nD=M\n@UP\nD;JNE\n@WAIT\n0;JMP";
+        let mut computer = Computer::new(&assembler::assemble(source).unwrap().words).unwrap();
+        computer.ram_mut()[1] = 100;
+        let keys = "100 press h\n200 release\n300 press i\n400 release\n500 press newline\n600 release";
+        let keys = KeyScript::from_string("k.txt", keys).unwrap();
+        assert_eq!(keys.run(&mut computer, 350), Ok(Stop::CycleLimit));
+        assert_eq!((computer.cycles(), computer.ram()[1]), (350, 102));
+        assert_eq!(keys.run(&mut computer, 1000), Ok(Stop::CycleLimit));
+        assert_eq!(computer.ram()[100..104], [104, 105, 128, 0]);
+    }
+
+    #[test]
+    fn play_pong() {
+        // The game starts after about 5 million cycles with the bat at x=230 on row 230, moving right
+        // until a key turns it around
+        let program = assembler::assemble(include_str!("../../../06/pong/Pong.asm")).unwrap();
+        let bat = |computer: &Computer| (0..512).find(|x| pixel(computer.screen(), *x, 230));
+        let mut still = Computer::new(&program.words).unwrap();
+        assert_eq!(KeyScript::default().run(&mut still, 6_000_000), Ok(Stop::CycleLimit));
+        assert_eq!(bat(&still), Some(290));
+        let mut moved = Computer::new(&program.words).unwrap();
+        let left = KeyScript::from_string("k.txt", "5000000 press left\n5500000 release").unwrap();
+        assert_eq!(left.run(&mut moved, 6_000_000), Ok(Stop::CycleLimit));
+        assert_eq!(bat(&moved), Some(174));
+    }
+}
@@ -0,0 +1,21 @@
+mod cpu;
+mod debugger;
+mod error;
+mod keys;
+mod profile;
+mod runner;
+mod screen;
+mod script;
+#[cfg(test)]
+mod tempdir;
+mod trace;
+
+pub use cpu::{Computer, Stop, KBD, RAM_SIZE, ROM_SIZE, SCREEN, SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use debugger::Debugger;
+pub use error::{CpuError, DebugError, LoadError, ScriptError, ScriptErrorKind};
+pub use keys::{KeyEvent, KeyScript};
+pub use profile::Profile;
+pub use screen::{pixel, save_screen, to_png, to_ppm};
+pub use runner::{run_script, Comparison, Outcome};
+pub use script::{parse_script, parse_value, Column, Command, CommandKind, Compare, Condition};
+pub use trace::{compare_trace, Trace, TraceFormat};
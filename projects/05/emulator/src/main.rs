@@ -0,0 +1,170 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+    process,
+};
+
+use assembler::SymbolTable;
+use emulator::{Comparison, Computer, KeyScript, Stop, Trace, TraceFormat};
+
+// Run a test script, writing its output file and reporting the comparison like the official tools
+fn test(path: &str) -> ! {
+    let outcome = emulator::run_script(Path::new(path)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    if let Some(output_file) = &outcome.output_file {
+        fs::write(output_file, &outcome.output).unwrap();
+    }
+    match outcome.comparison {
+        Comparison::NotCompared => println!("End of script"),
+        Comparison::Passed => println!("End of script - Comparison ended successfully"),
+        Comparison::Failed { line, expected, actual } => {
+            println!("Comparison failure at line {}", line);
+            println!("expected: {}", expected);
+            println!("  actual: {}", actual);
+            process::exit(1);
+        },
+    }
+    process::exit(0);
+}
+
+fn main() {
+    // Run a program until it halts or hits the cycle limit, then show the registers and R0-R15. Test
+    // scripts are run instead when given a `.tst` file.
+    //
+    // --screen=out.png writes the screen at the end of the run, as a PNG or PPM by its extension, and
+    // --frames=N also writes it every N cycles as out-00001.png, out-00002.png, ... --keys=keys.txt presses
+    // and releases keys at the cycles given in the file.
+    //
+    // --profile=prof.txt counts the instructions run, and writes a report of where the time went to prof.txt
+    // and the call stacks for flame graph tools to prof.folded. Labels and functions are named from the
+    // `.sym` file next to the program, which the assembler writes with --symbols. Calls are followed in
+    // VM translated code, where functions have labels like `Main.main`.
+    //
+    // --trace=trace.out writes a row for each half cycle in the layout of 05/CPU.cmp, or of CPU-external.cmp
+    // with --trace-external, and --trace-compare=CPU.cmp compares the trace with it like a test script.
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (flags, args): (Vec<&String>, Vec<&String>) = args.iter().partition(|a| a.starts_with("--"));
+    let screen_path = flags.iter().find_map(|f| f.strip_prefix("--screen=")).map(Path::new);
+    let frame_cycles: Option<u64> = flags
+        .iter()
+        .find_map(|f| f.strip_prefix("--frames="))
+        .map(|n| n.parse().expect("The number of cycles between frames must be a number"));
+    let keys = match flags.iter().find_map(|f| f.strip_prefix("--keys=")) {
+        Some(keys_path) => {
+            let source = fs::read_to_string(keys_path).unwrap();
+            KeyScript::from_string(keys_path, &source).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                process::exit(1);
+            })
+        },
+        None => KeyScript::default(),
+    };
+    let profile_path = flags.iter().find_map(|f| f.strip_prefix("--profile=")).map(Path::new);
+    let trace_path = flags.iter().find_map(|f| f.strip_prefix("--trace=")).map(Path::new);
+    let trace_cmp = flags.iter().find_map(|f| f.strip_prefix("--trace-compare="));
+    let trace_format = match flags.iter().any(|f| *f == "--trace-external") {
+        true => TraceFormat::CpuExternal,
+        false => TraceFormat::Cpu,
+    };
+    let path = args.first().expect("Please supply a .hack or .tst file as the first argument");
+    if path.ends_with(".tst") {
+        test(path);
+    }
+    let max_cycles = match args.get(1) {
+        Some(cycles) => cycles.parse().expect("The cycle limit must be a number"),
+        None => 1_000_000,
+    };
+    let source = fs::read_to_string(path).unwrap();
+    let mut computer = Computer::from_hack(path, &source).unwrap_or_else(|e| {
+        eprintln!("{}\n", e);
+        process::exit(1);
+    });
+    if profile_path.is_some() {
+        computer.start_profile();
+    }
+    if trace_path.is_some() || trace_cmp.is_some() {
+        let out: Box<dyn Write> = match trace_path {
+            Some(trace_path) => Box::new(BufWriter::new(File::create(trace_path).unwrap_or_else(|e| {
+                eprintln!("error: could not write {}: {}", trace_path.display(), e);
+                process::exit(1);
+            }))),
+            None => Box::new(io::sink()),
+        };
+        let cmp = trace_cmp.map(|trace_cmp| fs::read_to_string(trace_cmp).unwrap());
+        computer.start_trace(Trace::new(trace_format, out, cmp.as_deref()));
+    }
+
+    let save = |computer: &Computer, path: &Path| {
+        emulator::save_screen(path, computer.screen()).unwrap_or_else(|e| {
+            eprintln!("error: could not write {}: {}", path.display(), e);
+            process::exit(1);
+        })
+    };
+    let result = match (frame_cycles, screen_path) {
+        (Some(every), Some(screen_path)) if every > 0 => {
+            let stem = screen_path.file_stem().unwrap_or_default().to_string_lossy();
+            let ext = screen_path.extension().unwrap_or_default().to_string_lossy();
+            let mut frame = 0;
+            loop {
+                let cycles = every.min(max_cycles - computer.cycles());
+                let result = keys.run(&mut computer, cycles);
+                frame += 1;
+                save(&computer, &screen_path.with_file_name(format!("{}-{:05}.{}", stem, frame, ext)));
+                match result {
+                    Ok(Stop::CycleLimit) if computer.cycles() < max_cycles => continue,
+                    result => break result,
+                }
+            }
+        },
+        _ => keys.run(&mut computer, max_cycles),
+    };
+    if let Some(screen_path) = screen_path {
+        save(&computer, screen_path);
+    }
+    if let (Some(profile_path), Some(profile)) = (profile_path, computer.profile()) {
+        let sym_path = Path::new(path).with_extension("sym");
+        let symbols = match fs::read_to_string(&sym_path) {
+            Ok(sym) => SymbolTable::from_sym(&sym_path.display().to_string(), &sym).unwrap_or_else(|errors| {
+                for e in errors {
+                    eprintln!("{}\n", e);
+                }
+                process::exit(1);
+            }),
+            Err(_) => SymbolTable::new(),
+        };
+        fs::write(profile_path, profile.report(&symbols)).unwrap();
+        fs::write(profile_path.with_extension("folded"), profile.collapsed(&symbols)).unwrap();
+    }
+    if let Some(trace) = computer.trace_mut() {
+        match trace.finish() {
+            Ok(Comparison::Failed { line, expected, actual }) => {
+                println!("Comparison failure at line {}", line);
+                println!("expected: {}", expected);
+                println!("  actual: {}", actual);
+                process::exit(1);
+            },
+            Ok(Comparison::Passed) => println!("Comparison ended successfully"),
+            Ok(Comparison::NotCompared) => {},
+            Err(e) => {
+                eprintln!("error: could not write {}: {}", trace_path.unwrap().display(), e);
+                process::exit(1);
+            },
+        }
+    }
+    match result {
+        Ok(Stop::Halted) => println!("halted after {} cycles", computer.cycles()),
+        Ok(Stop::CycleLimit) => println!("stopped after {} cycles", computer.cycles()),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        },
+    }
+    println!("A={} D={} PC={}", computer.a(), computer.d(), computer.pc());
+    for (register, value) in computer.ram()[..16].iter().enumerate() {
+        println!("R{}={}", register, value);
+    }
+}
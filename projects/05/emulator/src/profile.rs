@@ -0,0 +1,299 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+};
+
+use assembler::{SymbolKind, SymbolTable};
+
+use crate::cpu::ROM_SIZE;
+
+// How many rows each table of the report shows
+const REPORT_ROWS: usize = 20;
+
+// A function being run, found by following the VM calling convention: a call sets LCL above where it was
+// and then jumps to the function, and a return sets LCL back and jumps to the return address. The code
+// between the last jump and the next one belongs to the frame the jump left, so the work of calling and
+// returning is counted towards the caller and the callee.
+//
+// Only VM code follows that convention, and a hand written program may well use LCL, which is R1, for
+// something else. So the report only shows a frame as a function when it was entered at a VM function's
+// label, like `Main.main`, and counts any other frame towards its caller.
+struct Frame {
+    lcl: u16,
+    // The node of the call tree for this frame
+    node: usize,
+}
+
+// A function in the call tree: the ROM address it was entered at and the node of its caller. The root is
+// the code that runs before any call, entered at address 0.
+struct Node {
+    parent: usize,
+    entry: u16,
+}
+
+// What a program spent its cycles on: how many times each ROM address was executed, and the call stack
+// each instruction was executed with. A computer collects this once `Computer::start_profile` is called.
+pub struct Profile {
+    counts: Vec<u64>,
+    frames: Vec<Frame>,
+    nodes: Vec<Node>,
+    children: HashMap<(usize, u16), usize>,
+    // Executions by call tree node and address
+    samples: HashMap<(usize, u16), u64>,
+}
+
+impl Profile {
+    pub fn new(lcl: u16) -> Self {
+        Profile {
+            counts: vec![0; ROM_SIZE],
+            frames: vec![Frame { lcl, node: 0 }],
+            nodes: vec![Node { parent: 0, entry: 0 }],
+            children: HashMap::new(),
+            samples: HashMap::new(),
+        }
+    }
+
+    // Executions of each ROM address
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    // Count the instruction at `pc`, which left the program counter at `next` and LCL at `lcl`
+    pub(crate) fn record(&mut self, pc: u16, next: u16, lcl: u16) {
+        let frame = self.frames.last().unwrap();
+        self.counts[pc as usize] += 1;
+        *self.samples.entry((frame.node, pc)).or_default() += 1;
+        if next == pc.wrapping_add(1) {
+            return;
+        }
+
+        while self.frames.len() > 1 && self.frames.last().unwrap().lcl > lcl {
+            self.frames.pop();
+        }
+        let frame = self.frames.last_mut().unwrap();
+        if lcl > frame.lcl {
+            let parent = frame.node;
+            let nodes = &mut self.nodes;
+            let node = *self.children.entry((parent, next)).or_insert_with(|| {
+                nodes.push(Node { parent, entry: next });
+                nodes.len() - 1
+            });
+            self.frames.push(Frame { lcl, node });
+        } else {
+            frame.lcl = lcl;
+        }
+    }
+
+    // The functions by the cycles spent in them, then their callees, then the labels and addresses that
+    // were executed most
+    pub fn report(&self, symbols: &SymbolTable) -> String {
+        let names = Names::new(symbols);
+        let total = self.total();
+        let percent = |cycles: u64| cycles as f64 * 100.0 / total.max(1) as f64;
+        let mut out = format!("{} cycles\n", total);
+
+        // A recursive function's cycles are only counted once towards its total
+        let mut functions: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+        for ((node, _), cycles) in &self.samples {
+            let stack = self.stack(*node, &names);
+            functions.entry(stack.last().unwrap().clone()).or_default().0 += cycles;
+            let mut seen: Vec<&String> = Vec::new();
+            for function in &stack {
+                if !seen.contains(&function) {
+                    functions.entry(function.clone()).or_default().1 += cycles;
+                    seen.push(function);
+                }
+            }
+        }
+        out += &format!("\n{:<40}{:>14}{:>8}{:>14}{:>8}\n", "function", "self", "%", "total", "%");
+        for (name, (own, all)) in top(functions.into_iter().map(|(name, cycles)| (name, cycles.0, cycles))) {
+            out += &format!("{:<40}{:>14}{:>8.2}{:>14}{:>8.2}\n", name, own, percent(own), all, percent(all));
+        }
+
+        let mut labels: BTreeMap<String, u64> = BTreeMap::new();
+        for (address, count) in self.counts.iter().enumerate().filter(|(_, count)| **count > 0) {
+            *labels.entry(names.label(address).unwrap_or_else(|| "-".to_string())).or_default() += count;
+        }
+        out += &format!("\n{:<40}{:>14}{:>8}\n", "label", "cycles", "%");
+        for (name, cycles) in top(labels.into_iter().map(|(name, cycles)| (name, cycles, cycles))) {
+            out += &format!("{:<40}{:>14}{:>8.2}\n", name, cycles, percent(cycles));
+        }
+
+        out += &format!("\n{:<40}{:>14}{:>8}\n", "address", "count", "%");
+        let addresses = self.counts.iter().enumerate().filter(|(_, count)| **count > 0);
+        for (address, count) in top(addresses.map(|(address, count)| (address, *count, *count))) {
+            let name = format!("ROM[{}] {}", address, names.location(address));
+            out += &format!("{:<40}{:>14}{:>8.2}\n", name.trim_end(), count, percent(count));
+        }
+        out
+    }
+
+    // The collapsed stack format that flame graph tools read: each call stack with the label executing at
+    // the top, separated by `;`, then the cycles spent there
+    pub fn collapsed(&self, symbols: &SymbolTable) -> String {
+        let names = Names::new(symbols);
+        let mut stacks: BTreeMap<String, u64> = BTreeMap::new();
+        for ((node, pc), cycles) in &self.samples {
+            let mut stack = self.stack(*node, &names);
+            if let Some(label) = names.label(*pc as usize).filter(|label| label != stack.last().unwrap()) {
+                stack.push(label);
+            }
+            *stacks.entry(stack.join(";")).or_default() += cycles;
+        }
+        stacks.iter().map(|(stack, cycles)| format!("{} {}\n", stack, cycles)).collect()
+    }
+
+    // The names of the functions from the root of the call tree to `node`
+    fn stack(&self, mut node: usize, names: &Names) -> Vec<String> {
+        let mut stack = Vec::new();
+        loop {
+            if node == 0 {
+                stack.push("(start)".to_string());
+                break;
+            }
+            stack.extend(names.function(self.nodes[node].entry as usize));
+            node = self.nodes[node].parent;
+        }
+        stack.reverse();
+        stack
+    }
+}
+
+// The `REPORT_ROWS` items with the highest counts, the first count deciding and the second shown
+fn top<K, V>(items: impl Iterator<Item = (K, u64, V)>) -> Vec<(K, V)> {
+    let mut items: Vec<(K, u64, V)> = items.collect();
+    items.sort_by_key(|item| Reverse(item.1));
+    items.into_iter().take(REPORT_ROWS).map(|(key, _, value)| (key, value)).collect()
+}
+
+// Labels ordered by address, for naming ROM addresses
+struct Names(Vec<(usize, String)>);
+
+impl Names {
+    fn new(symbols: &SymbolTable) -> Self {
+        let mut labels: Vec<(usize, String)> = symbols
+            .sorted()
+            .into_iter()
+            .filter(|(_, _, kind)| *kind == SymbolKind::Label)
+            .map(|(name, address, _)| (address, name.to_string()))
+            .collect();
+        labels.sort();
+        Names(labels)
+    }
+
+    // The last label at or before `address`
+    fn label(&self, address: usize) -> Option<String> {
+        let idx = self.0.partition_point(|(label, _)| *label <= address);
+        idx.checked_sub(1).map(|idx| self.0[idx].1.clone())
+    }
+
+    // The VM function starting at `address`, named `Class.function`. Labels inside functions are named
+    // `Class.function$label`.
+    fn function(&self, address: usize) -> Option<String> {
+        let idx = self.0.partition_point(|(label, _)| *label < address);
+        self.0[idx..]
+            .iter()
+            .take_while(|(label, _)| *label == address)
+            .find(|(_, name)| name.contains('.') && !name.contains('$') && !name.starts_with('.'))
+            .map(|(_, name)| name.clone())
+    }
+
+    // `LABEL+offset`, or nothing before the first label
+    fn location(&self, address: usize) -> String {
+        let idx = self.0.partition_point(|(label, _)| *label <= address);
+        match idx.checked_sub(1).map(|idx| &self.0[idx]) {
+            Some((label, name)) if *label == address => name.clone(),
+            Some((label, name)) => format!("{}+{}", name, address - label),
+            None => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Computer, Stop};
+
+    #[test]
+    fn count_loop() {
+        let assembled = assembler::assemble("@3\nD=A\n(LOOP)\nD=D-1\n@LOOP\nD;JGT\n(END)\n@END\n0;JMP").unwrap();
+        let mut computer = Computer::new(&assembled.words).unwrap();
+        computer.start_profile();
+        assert_eq!(computer.run(100), Ok(Stop::Halted));
+        let profile = computer.profile().unwrap();
+        assert_eq!(profile.counts()[..7], [1, 1, 3, 3, 3, 0, 0]);
+        assert_eq!(profile.total(), 11);
+        assert_eq!(profile.collapsed(&assembled.symbols), "(start) 2\n(start);LOOP 9\n");
+        let report = profile.report(&assembled.symbols);
+        assert!(report.starts_with("11 cycles\n"));
+        assert!(report.contains(&format!("\n{:<40}{:>14}{:>8.2}\n", "LOOP", 9, 9.0 * 100.0 / 11.0)));
+        assert!(report.contains(&format!("\n{:<40}{:>14}{:>8.2}\n", "ROM[3] LOOP+1", 3, 3.0 * 100.0 / 11.0)));
+    }
+
+    #[test]
+    fn r1_as_a_variable() {
+        // R1 counts up, which looks like a call at every jump, but LOOP isn't a VM function
+        let assembled = assembler::assemble("@R1\nM=0\n(LOOP)\n@R1\nM=M+1\n@LOOP\n0;JMP").unwrap();
+        let mut computer = Computer::new(&assembled.words).unwrap();
+        computer.start_profile();
+        assert_eq!(computer.run(42), Ok(Stop::CycleLimit));
+        let profile = computer.profile().unwrap();
+        assert_eq!(profile.collapsed(&assembled.symbols), "(start) 2\n(start);LOOP 40\n");
+        assert!(profile.report(&assembled.symbols).contains(&format!("\n{:<40}{:>14}", "(start)", 42)));
+    }
+
+    #[test]
+    fn vm_calls() {
+        // Main.main calls Math.double twice, with Sys.init calling Main.main, in the code the VM translator
+        // writes for `call` and `return`
+        let call = |function: &str, args: usize, ret: &str| {
+            format!(
+                "@{ret}\nD=A\n@SP\nAM=M+1\nA=A-1\nM=D\n\
+                 @LCL\nD=M\n@SP\nAM=M+1\nA=A-1\nM=D\n@ARG\nD=M\n@SP\nAM=M+1\nA=A-1\nM=D\n\
+                 @THIS\nD=M\n@SP\nAM=M+1\nA=A-1\nM=D\n@THAT\nD=M\n@SP\nAM=M+1\nA=A-1\nM=D\n\
+                 @SP\nD=M\n@{offset}\nD=D-A\n@ARG\nM=D\n@SP\nD=M\n@LCL\nM=D\n@{function}\n0;JMP\n({ret})\n",
+                offset = args + 5
+            )
+        };
+        let ret = "@LCL\nD=M\n@R13\nM=D\n@5\nA=D-A\nD=M\n@R14\nM=D\n@SP\nAM=M-1\nD=M\n@ARG\nA=M\nM=D\n\
+                   @ARG\nD=M+1\n@SP\nM=D\n@R13\nAM=M-1\nD=M\n@THAT\nM=D\n@R13\nAM=M-1\nD=M\n@THIS\nM=D\n\
+                   @R13\nAM=M-1\nD=M\n@ARG\nM=D\n@R13\nAM=M-1\nD=M\n@LCL\nM=D\n@R14\nA=M\n0;JMP\n";
+        let push = |n: usize| format!("@{}\nD=A\n@SP\nAM=M+1\nA=A-1\nM=D\n", n);
+        let source = format!(
+            "@256\nD=A\n@SP\nM=D\n{}(END)\n@END\n0;JMP\n\
+             (Sys.init)\n{}(Sys.init$END)\n@Sys.init$END\n0;JMP\n\
+             (Main.main)\n{}{}{}{}\
+             (Math.double)\n@ARG\nA=M\nD=M\nD=D+M\n@SP\nAM=M+1\nA=A-1\nM=D\n{}",
+            call("Sys.init", 0, "RET0"),
+            call("Main.main", 0, "RET1"),
+            push(3),
+            call("Math.double", 1, "RET2"),
+            call("Math.double", 1, "RET3"),
+            ret,
+            ret,
+        );
+        let assembled = assembler::assemble(&source).unwrap();
+        let mut computer = Computer::new(&assembled.words).unwrap();
+        computer.start_profile();
+        assert_eq!(computer.run(10_000), Ok(Stop::Halted));
+        assert_eq!((computer.ram()[0], computer.ram()[261]), (262, 12));
+        // The 4 instructions that set SP, then 42 for each call sequence, 8 for the body of Math.double and
+        // 42 for each return. Code after a return label belongs to the function the label is in.
+        let collapsed = [
+            "(start) 46",
+            "(start);Sys.init 42",
+            "(start);Sys.init;Main.main 48",
+            "(start);Sys.init;Main.main;Math.double 100",
+            "(start);Sys.init;Main.main;RET2 42",
+            "(start);Sys.init;Main.main;RET3 42",
+        ];
+        assert_eq!(computer.profile().unwrap().collapsed(&assembled.symbols), collapsed.join("\n") + "\n");
+        let report = computer.profile().unwrap().report(&assembled.symbols);
+        let percent = |cycles: u64| cycles as f64 * 100.0 / 320.0;
+        let row = format!("\n{:<40}{:>14}{:>8.2}{:>14}{:>8.2}\n", "Main.main", 132, percent(132), 232, percent(232));
+        assert!(report.contains(&row), "{}", report);
+    }
+}
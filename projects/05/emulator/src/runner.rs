@@ -0,0 +1,372 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use crate::cpu::{Computer, KBD, RAM_SIZE, ROM_SIZE, SCREEN};
+use crate::error::{ScriptError, ScriptErrorKind};
+use crate::script::{parse_script, Column, Command, CommandKind};
+
+// How the output of a script compared to its `.cmp` file
+#[derive(Debug, PartialEq, Clone)]
+pub enum Comparison {
+    // The script has no `compare-to`
+    NotCompared,
+    Passed,
+    // The 1-based line of the output that differs, like the official tools report
+    Failed { line: usize, expected: String, actual: String },
+}
+
+// What running a script produced: the output, the file it should be written to, and the comparison
+#[derive(Debug, PartialEq, Clone)]
+pub struct Outcome {
+    pub output: String,
+    pub output_file: Option<PathBuf>,
+    pub comparison: Comparison,
+}
+
+// Run the test script at `path` against the CPU emulator. Scripts written for the CPU emulator load an
+// `.asm` or `.hack` program and use `A`, `D`, `PC`, `RAM[n]` and `ticktock`. Scripts written for the
+// hardware simulator's `Computer.hdl` are run the same way, with its `ARegister[]`, `DRegister[]`,
+// `PC[]`, `RAM16K[n]`, `reset` pin and separate `tick` and `tock`. `echo` prints to stdout.
+pub fn run_script(path: &Path) -> Result<Outcome, ScriptError> {
+    let file = path.display().to_string();
+    let source = fs::read_to_string(path)
+        .map_err(|e| ScriptError::new(&file, 0, ScriptErrorKind::FileFailed(file.clone(), e.kind())))?;
+    let commands = parse_script(&file, &source)?;
+    let mut runner = Runner {
+        file: &file,
+        dir: path.parent().unwrap_or_else(|| Path::new("")).to_path_buf(),
+        computer: None,
+        reset: false,
+        time: 0,
+        ticked: false,
+        columns: Vec::new(),
+        output: String::new(),
+        output_file: None,
+        compare: None,
+        comparison: Comparison::NotCompared,
+    };
+    runner.run(&commands)?;
+    Ok(Outcome { output: runner.output, output_file: runner.output_file, comparison: runner.comparison })
+}
+
+// Returned by commands to stop the script early when the output stops matching
+struct Mismatch;
+
+struct Runner<'a> {
+    file: &'a str,
+    dir: PathBuf,
+    computer: Option<Computer>,
+    reset: bool,
+    time: u64,
+    // Whether the clock is between a tick and its tock
+    ticked: bool,
+    columns: Vec<Column>,
+    output: String,
+    output_file: Option<PathBuf>,
+    // The lines of the `.cmp` file, and how many output lines have been compared with them
+    compare: Option<(Vec<String>, usize)>,
+    comparison: Comparison,
+}
+
+impl Runner<'_> {
+    fn run(&mut self, commands: &[Command]) -> Result<(), ScriptError> {
+        match self.commands(commands) {
+            Ok(()) | Err(Ok(Mismatch)) => Ok(()),
+            Err(Err(e)) => Err(e),
+        }
+    }
+
+    fn commands(&mut self, commands: &[Command]) -> Result<(), Result<Mismatch, ScriptError>> {
+        for command in commands {
+            self.command(command)?;
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, command: &Command) -> Result<(), Result<Mismatch, ScriptError>> {
+        let error = |kind| Err(ScriptError::new(self.file, command.line, kind));
+        match &command.kind {
+            CommandKind::Load(name) if name.ends_with(".hdl") => {
+                if name != "Computer.hdl" {
+                    return Err(error(ScriptErrorKind::Unsupported(name.clone())));
+                }
+                self.start(Computer::new(&[]).unwrap());
+            },
+            CommandKind::Load(name) | CommandKind::LoadRom(name) => {
+                let computer = self.load(name).map_err(error)?;
+                self.start(computer);
+            },
+            CommandKind::OutputFile(name) => self.output_file = Some(self.dir.join(name)),
+            CommandKind::CompareTo(name) => {
+                let path = self.dir.join(name);
+                let text = fs::read_to_string(&path)
+                    .map_err(|e| error(ScriptErrorKind::FileFailed(path.display().to_string(), e.kind())))?;
+                self.compare = Some((text.lines().map(|l| l.to_string()).collect(), 0));
+                self.comparison = Comparison::Passed;
+            },
+            CommandKind::OutputList(columns) => {
+                self.columns = columns.clone();
+                let header = columns.iter().map(header).collect::<String>() + "|";
+                self.write(header)?;
+            },
+            CommandKind::Set(variable, value) => self.set(variable, *value).map_err(error)?,
+            CommandKind::Tick => self.ticked = true,
+            CommandKind::Tock => self.tock().map_err(error)?,
+            CommandKind::TickTock => {
+                self.ticked = true;
+                self.tock().map_err(error)?;
+            },
+            CommandKind::Output => {
+                let mut line = String::new();
+                for column in &self.columns.clone() {
+                    let value = self.get(&column.variable).map_err(error)?;
+                    let text = if column.variable == "time" { self.time() } else { value.to_string() };
+                    line += &cell(column, value, &text);
+                }
+                self.write(line + "|")?;
+            },
+            CommandKind::VmStep => return Err(error(ScriptErrorKind::Unsupported("vmstep".to_string()))),
+            CommandKind::Echo(text) => println!("{}", text),
+            CommandKind::ClearEcho | CommandKind::Ignored => {},
+            CommandKind::Repeat(Some(count), commands) => {
+                for _ in 0..*count {
+                    self.commands(commands)?;
+                }
+            },
+            CommandKind::Repeat(None, commands) => loop {
+                self.commands(commands)?;
+            },
+            CommandKind::While(condition, commands) => {
+                while condition.holds(self.get(&condition.variable).map_err(error)? as i16 as i32) {
+                    self.commands(commands)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn start(&mut self, computer: Computer) {
+        self.computer = Some(computer);
+        self.time = 0;
+        self.ticked = false;
+    }
+
+    // A program for the computer, assembling it first if it is written in assembly
+    fn load(&self, name: &str) -> Result<Computer, ScriptErrorKind> {
+        let path = self.dir.join(name);
+        let file = path.display().to_string();
+        let is_asm = name.ends_with(".asm");
+        if !is_asm && !name.ends_with(".hack") {
+            return Err(ScriptErrorKind::Unsupported(name.to_string()));
+        }
+        let source = fs::read_to_string(&path).map_err(|e| ScriptErrorKind::FileFailed(file.clone(), e.kind()))?;
+        let words = if is_asm {
+            assembler::parse(&file, &source).and_then(|program| assembler::resolve(&program)).map(|a| a.words)
+        } else {
+            assembler::parse_hack(&file, &source)
+        };
+        let words = words.map_err(|errors| {
+            ScriptErrorKind::AssemblyFailed(errors.iter().map(|e| format!("{}\n", e)).collect())
+        })?;
+        // Loading into a computer that is already running keeps its memory, like `ROM32K load`
+        let mut computer = Computer::new(&words).map_err(|e| ScriptErrorKind::AssemblyFailed(format!("{}\n", e)))?;
+        if let Some(running) = &self.computer {
+            computer.ram_mut().copy_from_slice(running.ram());
+        }
+        Ok(computer)
+    }
+
+    fn computer(&mut self) -> Result<&mut Computer, ScriptErrorKind> {
+        self.computer.as_mut().ok_or(ScriptErrorKind::NoProgram)
+    }
+
+    // The second half of a clock cycle, when the computer executes an instruction
+    fn tock(&mut self) -> Result<(), ScriptErrorKind> {
+        let reset = self.reset;
+        let computer = self.computer()?;
+        computer.step().map_err(ScriptErrorKind::Cpu)?;
+        if reset {
+            computer.reset();
+        }
+        self.time += 1;
+        self.ticked = false;
+        Ok(())
+    }
+
+    fn time(&self) -> String {
+        format!("{}{}", self.time, if self.ticked { "+" } else { "" })
+    }
+
+    fn get(&mut self, variable: &str) -> Result<u16, ScriptErrorKind> {
+        if variable == "time" {
+            return Ok(self.time as u16);
+        }
+        if variable == "reset" {
+            return Ok(self.reset as u16);
+        }
+        let computer = self.computer()?;
+        Ok(match register(variable) {
+            Some(Register::A) => computer.a(),
+            Some(Register::D) => computer.d(),
+            Some(Register::Pc) => computer.pc(),
+            Some(Register::Ram(address)) => computer.ram()[address],
+            Some(Register::Rom(address)) => computer.rom()[address],
+            None => return Err(ScriptErrorKind::UnknownVariable(variable.to_string())),
+        })
+    }
+
+    fn set(&mut self, variable: &str, value: i32) -> Result<(), ScriptErrorKind> {
+        if variable == "reset" {
+            self.reset = value != 0;
+            return Ok(());
+        }
+        let value = value as u16;
+        let computer = self.computer()?;
+        match register(variable) {
+            Some(Register::A) => computer.set_a(value),
+            Some(Register::D) => computer.set_d(value),
+            Some(Register::Pc) => computer.set_pc(value),
+            Some(Register::Ram(address)) => computer.ram_mut()[address] = value,
+            Some(Register::Rom(_)) | None => return Err(ScriptErrorKind::UnknownVariable(variable.to_string())),
+        }
+        Ok(())
+    }
+
+    // Add a line to the output, comparing it to the same line of the `.cmp` file
+    fn write(&mut self, line: String) -> Result<(), Result<Mismatch, ScriptError>> {
+        self.output += &line;
+        self.output += "\n";
+        let (expected, compared) = match &mut self.compare {
+            Some(compare) => compare,
+            None => return Ok(()),
+        };
+        let expected = expected.get(*compared).cloned().unwrap_or_default();
+        *compared += 1;
+        if !matches(&expected, &line) {
+            self.comparison = Comparison::Failed { line: *compared, expected: expected.trim_end().to_string(), actual: line };
+            return Err(Ok(Mismatch));
+        }
+        Ok(())
+    }
+}
+
+// Whether a line of output matches a line of a `.cmp` file. As with the official tools whitespace is
+// ignored, and a cell of `*`s matches anything.
+pub(crate) fn matches(expected: &str, actual: &str) -> bool {
+    let strip = |s: &str| s.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    let (expected, actual) = (strip(expected), strip(actual));
+    let (expected, actual): (Vec<&str>, Vec<&str>) = (expected.split('|').collect(), actual.split('|').collect());
+    expected.len() == actual.len()
+        && expected.iter().zip(&actual).all(|(e, a)| e == a || (!e.is_empty() && e.chars().all(|c| c == '*')))
+}
+
+enum Register {
+    A,
+    D,
+    Pc,
+    Ram(usize),
+    Rom(usize),
+}
+
+// The names used by the CPU emulator, and by the parts of Computer.hdl in the hardware simulator
+fn register(variable: &str) -> Option<Register> {
+    let indexed = |prefix: &str| -> Option<usize> {
+        variable.strip_prefix(prefix)?.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+    };
+    // The registers are single chips, so any index into them reads the whole register
+    let register = match variable {
+        "A" => Register::A,
+        "D" => Register::D,
+        _ if variable.starts_with("ARegister[") => Register::A,
+        _ if variable.starts_with("DRegister[") => Register::D,
+        "PC" | "PC[]" => Register::Pc,
+        "Keyboard[]" => Register::Ram(KBD),
+        _ => {
+            if let Some(address) = indexed("RAM").or_else(|| indexed("RAM16K")).filter(|a| *a < RAM_SIZE) {
+                Register::Ram(address)
+            } else if let Some(address) = indexed("Screen").filter(|a| *a < KBD - SCREEN) {
+                Register::Ram(SCREEN + address)
+            } else if let Some(address) = indexed("ROM").or_else(|| indexed("ROM32K")).filter(|a| *a < ROM_SIZE) {
+                Register::Rom(address)
+            } else {
+                return None;
+            }
+        },
+    };
+    Some(register)
+}
+
+// The column name centred in its cell, and cut short if it doesn't fit
+pub(crate) fn header(column: &Column) -> String {
+    let size = column.left + column.width + column.right;
+    let name: String = column.variable.chars().take(size).collect();
+    let left = (size - name.len()) / 2;
+    format!("|{}{}{}", " ".repeat(left), name, " ".repeat(size - name.len() - left))
+}
+
+// A value formatted by its column. Numbers are right aligned and strings, given as `text`, left aligned.
+pub(crate) fn cell(column: &Column, value: u16, text: &str) -> String {
+    let width = column.width;
+    let text = match column.format {
+        'B' => format!("{:0>width$}", format!("{:b}", value)),
+        'X' => format!("{:0>width$}", format!("{:X}", value)),
+        'D' => format!("{:>width$}", value as i16),
+        _ => format!("{:<width$}", text),
+    };
+    // Binary and hex keep their low digits, like a narrower register would
+    let text = if text.len() > width && "BX".contains(column.format) { text[text.len() - width..].to_string() } else { text };
+    format!("|{}{}{}", " ".repeat(column.left), text, " ".repeat(column.right))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{run_script, Comparison};
+    use crate::tempdir::TempDir;
+
+    #[test]
+    fn computer_scripts() {
+        for script in ["ComputerAdd.tst", "ComputerMax.tst", "ComputerRect.tst", "ComputerMax-external.tst"] {
+            let outcome = run_script(&Path::new("..").join(script)).unwrap();
+            assert_eq!(outcome.comparison, Comparison::Passed, "{}", script);
+        }
+    }
+
+    #[test]
+    fn cpu_emulator_scripts() {
+        for script in ["StackArithmetic/StackTest/StackTest.tst", "MemoryAccess/PointerTest/PointerTest.tst"] {
+            let outcome = run_script(&Path::new("../../07").join(script)).unwrap();
+            assert_eq!(outcome.comparison, Comparison::Passed, "{}", script);
+        }
+    }
+
+    #[test]
+    fn report_first_mismatch() {
+        let temp = TempDir::new("report_first_mismatch");
+        let dir = &temp.0;
+        std::fs::write(dir.join("Inc.asm"), "@R0\nM=M+1").unwrap();
+        std::fs::write(dir.join("Inc.cmp"), "|RAM[0]|\n|     1|\n|     3|\n").unwrap();
+        let script = "load Inc.asm, compare-to Inc.cmp, output-list RAM[0]%D1.4.1;\n\
+                      repeat 2 { ticktock; } output;\nset PC 0, repeat 2 { ticktock; } output;";
+        std::fs::write(dir.join("Inc.tst"), script).unwrap();
+        let outcome = run_script(&dir.join("Inc.tst")).unwrap();
+        let expected = Comparison::Failed { line: 3, expected: "|     3|".to_string(), actual: "|    2 |".to_string() };
+        assert_eq!(outcome.comparison, expected);
+    }
+
+    #[test]
+    fn wildcard_cells() {
+        let temp = TempDir::new("wildcard_cells");
+        let dir = &temp.0;
+        std::fs::write(dir.join("Inc.asm"), "@R0\nM=M+1").unwrap();
+        std::fs::write(dir.join("Inc.cmp"), "|RAM[0]| PC  |\n|******|  2  |\n|     2|*****|\n").unwrap();
+        let script = "load Inc.asm, compare-to Inc.cmp, output-list RAM[0]%D1.4.1 PC%D1.3.1;\n\
+                      repeat 2 { ticktock; } output;\nset PC 0, repeat 2 { ticktock; } output;";
+        std::fs::write(dir.join("Inc.tst"), script).unwrap();
+        assert_eq!(run_script(&dir.join("Inc.tst")).unwrap().comparison, Comparison::Passed);
+
+        // A wildcard only stands for one cell, and an empty cell isn't a wildcard
+        assert!(!super::matches("|***|", "|1|2|"));
+        assert!(!super::matches("| |", "|1|"));
+    }
+}
@@ -0,0 +1,152 @@
+use std::{fs, io, path::Path};
+
+use crate::cpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const WORDS_PER_ROW: usize = SCREEN_WIDTH / 16;
+
+// Whether the pixel at column x of row y is set (black). Each row is 32 words, and the least significant
+// bit of a word is its leftmost pixel.
+pub fn pixel(screen: &[u16], x: usize, y: usize) -> bool {
+    screen[y * WORDS_PER_ROW + x / 16] & (1 << (x % 16)) != 0
+}
+
+// A binary (P6) PPM image of the screen, black on white
+pub fn to_ppm(screen: &[u16]) -> Vec<u8> {
+    let mut out = format!("P6\n{} {}\n255\n", SCREEN_WIDTH, SCREEN_HEIGHT).into_bytes();
+    for y in 0..SCREEN_HEIGHT {
+        for x in 0..SCREEN_WIDTH {
+            let shade = if pixel(screen, x, y) { 0 } else { 255 };
+            out.extend([shade; 3]);
+        }
+    }
+    out
+}
+
+// A 1 bit grayscale PNG image of the screen. The image data is stored rather than compressed, which
+// keeps the encoder short and the files under 17K.
+pub fn to_png(screen: &[u16]) -> Vec<u8> {
+    // Each row starts with filter type 0 (none), then packs 8 pixels to a byte with the leftmost pixel in
+    // the most significant bit. PNG grayscale is 0 for black, so the bits are inverted.
+    let mut rows = Vec::with_capacity(SCREEN_HEIGHT * (1 + SCREEN_WIDTH / 8));
+    for row in screen.chunks(WORDS_PER_ROW).take(SCREEN_HEIGHT) {
+        rows.push(0);
+        for word in row {
+            let bits = !word.reverse_bits();
+            rows.extend(bits.to_be_bytes());
+        }
+    }
+
+    let mut header = Vec::new();
+    header.extend((SCREEN_WIDTH as u32).to_be_bytes());
+    header.extend((SCREEN_HEIGHT as u32).to_be_bytes());
+    // Bit depth 1, grayscale, deflate, no filtering beyond each row's, not interlaced
+    header.extend([1, 0, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib_stored(&rows));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// Write the screen as a PNG if the path ends in `.png`, and as a PPM otherwise
+pub fn save_screen(path: &Path, screen: &[u16]) -> io::Result<()> {
+    let image = match path.extension() {
+        Some(ext) if ext.eq_ignore_ascii_case("png") => to_png(screen),
+        _ => to_ppm(screen),
+    };
+    fs::write(path, image)
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+// A zlib stream holding `data` in uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = data.chunks(0xFFFF).collect();
+    for (idx, block) in blocks.iter().enumerate() {
+        let last = idx + 1 == blocks.len();
+        out.push(last as u8);
+        let len = block.len() as u16;
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(*block);
+    }
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{adler32, crc32, pixel, to_png, to_ppm};
+    use crate::{Computer, Stop};
+
+    fn rect() -> Computer {
+        let mut computer = Computer::from_hack("Rect.hack", include_str!("../../Rect.hack")).unwrap();
+        computer.ram_mut()[0] = 4;
+        assert_eq!(computer.run(10_000), Ok(Stop::Halted));
+        computer
+    }
+
+    #[test]
+    fn draw_rect() {
+        let computer = rect();
+        let screen = computer.screen();
+        for y in 0..6 {
+            let row: Vec<bool> = (0..18).map(|x| pixel(screen, x, y)).collect();
+            assert_eq!(row, (0..18).map(|x| y < 4 && x < 16).collect::<Vec<_>>());
+        }
+        let ppm = to_ppm(screen);
+        let header = b"P6\n512 256\n255\n";
+        assert_eq!(&ppm[..header.len()], header);
+        assert_eq!(ppm.len(), header.len() + 512 * 256 * 3);
+        assert_eq!(ppm[header.len()..header.len() + 3], [0, 0, 0]);
+        assert_eq!(ppm[header.len() + 16 * 3..header.len() + 17 * 3], [255, 255, 255]);
+    }
+
+    #[test]
+    fn encode_png() {
+        let png = to_png(rect().screen());
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        // The end chunk is always the same 12 bytes, including its well known CRC
+        assert_eq!(&png[png.len() - 12..], b"\0\0\0\0IEND\xAE\x42\x60\x82");
+        // The first row of image data: no filter, 16 black pixels, then white
+        let data = 8 + 25 + 8 + 2 + 5;
+        assert_eq!(&png[data..data + 4], [0, 0, 0, 0xFF]);
+        assert_eq!(png.len(), 8 + 25 + 12 + 2 + 5 + 256 * 65 + 4 + 12);
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+}
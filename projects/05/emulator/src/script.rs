@@ -0,0 +1,368 @@
+use crate::error::{ScriptError, ScriptErrorKind};
+
+// A command from a nand2tetris test script, and the line it starts on
+#[derive(Debug, PartialEq, Clone)]
+pub struct Command {
+    pub kind: CommandKind,
+    pub line: usize,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum CommandKind {
+    // `load Prog.asm`, `load Prog.hack` or `load Computer.hdl`
+    Load(String),
+    // `ROM32K load Prog.hack`, loading a program into the ROM of the loaded chip
+    LoadRom(String),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<Column>),
+    Set(String, i32),
+    Tick,
+    Tock,
+    TickTock,
+    // Steps the VM emulator, so it parses but can't be run
+    VmStep,
+    Output,
+    Echo(String),
+    ClearEcho,
+    // Commands that only change what the official tools show, like breakpoints, do nothing here
+    Ignored,
+    // Without a count, repeats forever
+    Repeat(Option<u32>, Vec<Command>),
+    While(Condition, Vec<Command>),
+}
+
+// One column of `output-list`, written `name%D1.6.1`: the variable, the format (Binary, Decimal, heX or
+// String) and the number of spaces to the left, characters of the value, and spaces to the right
+#[derive(Debug, PartialEq, Clone)]
+pub struct Column {
+    pub variable: String,
+    pub format: char,
+    pub left: usize,
+    pub width: usize,
+    pub right: usize,
+}
+
+impl Column {
+    pub(crate) fn from_string(input: &str) -> Option<Self> {
+        let (variable, format) = input.split_once('%')?;
+        let mut chars = format.chars();
+        let format = chars.next().filter(|c| "BDXS".contains(*c))?;
+        let sizes: Vec<usize> = chars.as_str().split('.').map(|n| n.parse().ok()).collect::<Option<_>>()?;
+        match sizes[..] {
+            [left, width, right] => Some(Column { variable: variable.to_string(), format, left, width, right }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Compare {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Condition {
+    pub variable: String,
+    pub compare: Compare,
+    pub value: i32,
+}
+
+impl Condition {
+    pub fn holds(&self, value: i32) -> bool {
+        match self.compare {
+            Compare::Eq => value == self.value,
+            Compare::Ne => value != self.value,
+            Compare::Lt => value < self.value,
+            Compare::Le => value <= self.value,
+            Compare::Gt => value > self.value,
+            Compare::Ge => value >= self.value,
+        }
+    }
+}
+
+// Numbers are decimal, or written `%D-5`, `%B0101` or `%X1F`
+pub fn parse_value(input: &str) -> Option<i32> {
+    let (radix, digits) = match input.strip_prefix('%') {
+        Some(rest) if rest.starts_with('D') => (10, &rest[1..]),
+        Some(rest) if rest.starts_with('B') => (2, &rest[1..]),
+        Some(rest) if rest.starts_with('X') => (16, &rest[1..]),
+        Some(_) => return None,
+        None => (10, input),
+    };
+    i32::from_str_radix(digits, radix).ok()
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Word(String),
+    Str(String),
+    // One of `,` `;` `{` `}`
+    Punct(char),
+}
+
+// Split a script into tokens with their line numbers, dropping `//` and `/* */` comments
+fn tokenize(file: &str, source: &str) -> Result<Vec<(Token, usize)>, ScriptError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {},
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.next_if(|c| *c != '\n').is_some() {}
+            },
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let start = line;
+                let mut last = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if last == '*' => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            last = c;
+                        },
+                        None => return Err(ScriptError::new(file, start, ScriptErrorKind::UnexpectedEnd)),
+                    }
+                }
+            },
+            ',' | ';' | '{' | '}' => tokens.push((Token::Punct(c), line)),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\n') | None => return Err(ScriptError::new(file, line, ScriptErrorKind::UnexpectedEnd)),
+                        Some(c) => text.push(c),
+                    }
+                }
+                tokens.push((Token::Str(text), line));
+            },
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !",;{}\"".contains(*c)) {
+                    word.push(c);
+                }
+                tokens.push((Token::Word(word), line));
+            },
+        }
+    }
+    Ok(tokens)
+}
+
+// Parse a test script. Commands are separated by `,` or `;`, which the official tools use to group
+// commands into steps for single stepping, so here they mean the same thing.
+pub fn parse_script(file: &str, source: &str) -> Result<Vec<Command>, ScriptError> {
+    let tokens = tokenize(file, source)?;
+    let mut parser = ScriptParser { file, tokens: &tokens, pos: 0 };
+    let commands = parser.commands()?;
+    match parser.next() {
+        Some((token, line)) => Err(ScriptError::new(file, line, ScriptErrorKind::UnexpectedToken(describe(token)))),
+        None => Ok(commands),
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Word(w) => w.clone(),
+        Token::Str(s) => format!("\"{}\"", s),
+        Token::Punct(c) => c.to_string(),
+    }
+}
+
+struct ScriptParser<'a> {
+    file: &'a str,
+    tokens: &'a [(Token, usize)],
+    pos: usize,
+}
+
+impl<'a> ScriptParser<'a> {
+    fn next(&mut self) -> Option<(&'a Token, usize)> {
+        let (token, line) = self.tokens.get(self.pos)?;
+        self.pos += 1;
+        Some((token, *line))
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    // The line of the last token read, for errors
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos.saturating_sub(1)).map_or(1, |(_, line)| *line)
+    }
+
+    fn error(&self, kind: ScriptErrorKind) -> ScriptError {
+        ScriptError::new(self.file, self.line(), kind)
+    }
+
+    fn word(&mut self) -> Result<&'a str, ScriptError> {
+        match self.next() {
+            Some((Token::Word(word), _)) => Ok(word),
+            Some((token, _)) => Err(self.error(ScriptErrorKind::UnexpectedToken(describe(token)))),
+            None => Err(self.error(ScriptErrorKind::UnexpectedEnd)),
+        }
+    }
+
+    fn value(&mut self) -> Result<i32, ScriptError> {
+        let word = self.word()?;
+        parse_value(word).ok_or_else(|| self.error(ScriptErrorKind::InvalidNumber(word.to_string())))
+    }
+
+    fn expect(&mut self, punct: char) -> Result<(), ScriptError> {
+        match self.next() {
+            Some((Token::Punct(c), _)) if *c == punct => Ok(()),
+            Some((token, _)) => Err(self.error(ScriptErrorKind::UnexpectedToken(describe(token)))),
+            None => Err(self.error(ScriptErrorKind::UnexpectedEnd)),
+        }
+    }
+
+    // Commands up to the end of the script or a closing `}`
+    fn commands(&mut self) -> Result<Vec<Command>, ScriptError> {
+        let mut commands = Vec::new();
+        while self.peek().is_some() && self.peek() != Some(&Token::Punct('}')) {
+            commands.push(self.command()?);
+        }
+        Ok(commands)
+    }
+
+    fn block(&mut self) -> Result<Vec<Command>, ScriptError> {
+        self.expect('{')?;
+        let commands = self.commands()?;
+        self.expect('}')?;
+        Ok(commands)
+    }
+
+    fn command(&mut self) -> Result<Command, ScriptError> {
+        let name = self.word()?;
+        let line = self.line();
+        let kind = match name {
+            // Loops end with their block rather than a separator
+            "repeat" => {
+                let count = match self.peek() {
+                    Some(Token::Word(_)) => Some(self.value()? as u32),
+                    _ => None,
+                };
+                return Ok(Command { kind: CommandKind::Repeat(count, self.block()?), line });
+            },
+            "while" => {
+                let variable = self.word()?.to_string();
+                let compare = match self.word()? {
+                    "=" => Compare::Eq,
+                    "<>" => Compare::Ne,
+                    "<" => Compare::Lt,
+                    "<=" => Compare::Le,
+                    ">" => Compare::Gt,
+                    ">=" => Compare::Ge,
+                    op => return Err(self.error(ScriptErrorKind::UnexpectedToken(op.to_string()))),
+                };
+                let value = self.value()?;
+                let condition = Condition { variable, compare, value };
+                return Ok(Command { kind: CommandKind::While(condition, self.block()?), line });
+            },
+            "load" => CommandKind::Load(self.word()?.to_string()),
+            "output-file" => CommandKind::OutputFile(self.word()?.to_string()),
+            "compare-to" => CommandKind::CompareTo(self.word()?.to_string()),
+            "output-list" => {
+                let mut columns = Vec::new();
+                while let Some(Token::Word(_)) = self.peek() {
+                    let spec = self.word()?;
+                    let column = Column::from_string(spec)
+                        .ok_or_else(|| self.error(ScriptErrorKind::InvalidFormat(spec.to_string())))?;
+                    columns.push(column);
+                }
+                CommandKind::OutputList(columns)
+            },
+            "set" => CommandKind::Set(self.word()?.to_string(), self.value()?),
+            "tick" => CommandKind::Tick,
+            "tock" => CommandKind::Tock,
+            "ticktock" => CommandKind::TickTock,
+            "vmstep" => CommandKind::VmStep,
+            "output" => CommandKind::Output,
+            "echo" => match self.next() {
+                Some((Token::Str(text), _)) => CommandKind::Echo(text.clone()),
+                Some((token, _)) => return Err(self.error(ScriptErrorKind::UnexpectedToken(describe(token)))),
+                None => return Err(self.error(ScriptErrorKind::UnexpectedEnd)),
+            },
+            "clear-echo" => CommandKind::ClearEcho,
+            "breakpoint" | "clear-breakpoints" | "eval" => {
+                while let Some(Token::Word(_)) = self.peek() {
+                    self.word()?;
+                }
+                CommandKind::Ignored
+            },
+            chip if matches!(self.peek(), Some(Token::Word(w)) if w == "load") => {
+                self.word()?;
+                let program = self.word()?.to_string();
+                if chip != "ROM32K" {
+                    return Err(self.error(ScriptErrorKind::Unsupported(format!("{} load {}", chip, program))));
+                }
+                CommandKind::LoadRom(program)
+            },
+            _ => return Err(self.error(ScriptErrorKind::UnknownCommand(name.to_string()))),
+        };
+        // The last command of a block or the script may leave out its separator
+        match self.peek() {
+            Some(Token::Punct(',' | ';')) => self.pos += 1,
+            Some(Token::Punct('}')) | None => {},
+            Some(token) => return Err(self.error(ScriptErrorKind::UnexpectedToken(describe(token)))),
+        }
+        Ok(Command { kind, line })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_script, parse_value, Column, CommandKind, Compare, Condition};
+    use crate::error::ScriptErrorKind;
+
+    #[test]
+    fn parse_commands() {
+        let source = "load Max.asm, /* a\ncomment */ output-list RAM[0]%D2.6.2\n  time%S1.4.1;\nset RAM[0] %X1F,\n\
+                      repeat 3 { ticktock; }\nwhile out <> 75 { tick, tock, }\nrepeat { output; }";
+        let commands = parse_script("t.tst", source).unwrap();
+        let kinds: Vec<(usize, CommandKind)> = commands.into_iter().map(|c| (c.line, c.kind)).collect();
+        let column = |variable: &str, format, left, width, right| Column { variable: variable.to_string(), format, left, width, right };
+        let condition = Condition { variable: "out".to_string(), compare: Compare::Ne, value: 75 };
+        assert_eq!(kinds, [
+            (1, CommandKind::Load("Max.asm".to_string())),
+            (2, CommandKind::OutputList(vec![column("RAM[0]", 'D', 2, 6, 2), column("time", 'S', 1, 4, 1)])),
+            (4, CommandKind::Set("RAM[0]".to_string(), 31)),
+            (5, CommandKind::Repeat(Some(3), vec![super::Command { kind: CommandKind::TickTock, line: 5 }])),
+            (6, CommandKind::While(condition, vec![
+                super::Command { kind: CommandKind::Tick, line: 6 },
+                super::Command { kind: CommandKind::Tock, line: 6 },
+            ])),
+            (7, CommandKind::Repeat(None, vec![super::Command { kind: CommandKind::Output, line: 7 }])),
+        ]);
+    }
+
+    #[test]
+    fn parse_values() {
+        assert_eq!(parse_value("-1"), Some(-1));
+        assert_eq!(parse_value("%B0101"), Some(5));
+        assert_eq!(parse_value("%D-32768"), Some(-32768));
+        assert_eq!(parse_value("%Q1"), None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |source| parse_script("t.tst", source).unwrap_err();
+        assert_eq!(error("load A.asm,\nfoo;").kind, ScriptErrorKind::UnknownCommand("foo".to_string()));
+        assert_eq!(error("load A.asm,\nfoo;").line, 2);
+        assert_eq!(error("output-list RAM[0]%D1.6;").kind, ScriptErrorKind::InvalidFormat("RAM[0]%D1.6".to_string()));
+        assert_eq!(error("repeat 2 { ticktock;").kind, ScriptErrorKind::UnexpectedEnd);
+        assert_eq!(error("set RAM[0] x;").kind, ScriptErrorKind::InvalidNumber("x".to_string()));
+        assert_eq!(error("output }").kind, ScriptErrorKind::UnexpectedToken("}".to_string()));
+        assert_eq!(error("output output").kind, ScriptErrorKind::UnexpectedToken("output".to_string()));
+    }
+}
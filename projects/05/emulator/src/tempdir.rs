@@ -0,0 +1,18 @@
+use std::{env, fs, path::PathBuf, process};
+
+// A directory for one test in one run of the tests, removed once the test is done with it
+pub(crate) struct TempDir(pub PathBuf);
+
+impl TempDir {
+    pub fn new(test: &str) -> Self {
+        let dir = env::temp_dir().join(format!("emulator-{}-{}", test, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,231 @@
+use std::io::{self, Write};
+
+use assembler::Instr;
+
+use crate::cpu::alu;
+use crate::runner::{cell, header, matches, Comparison};
+use crate::script::Column;
+
+// The output lists of `05/CPU.tst` and `05/CPU-external.tst`
+const CPU_COLUMNS: &str = "time%S0.4.0 inM%D0.6.0 instruction%B0.16.0 reset%B2.1.2 outM%D1.6.0 writeM%B3.1.3 \
+                           addressM%D0.5.0 pc%D0.5.0 DRegister[]%D1.6.1";
+
+// Which of the CPU test's layouts a trace is written in
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TraceFormat {
+    // `CPU.cmp`, which shows the D register
+    Cpu,
+    // `CPU-external.cmp`, which only shows the pins of the chip
+    CpuExternal,
+}
+
+// The A, D and PC registers
+pub(crate) type Registers = (u16, u16, u16);
+
+// The pins of the CPU chip, and its D register, as the hardware simulator shows them every half cycle
+struct Row {
+    in_m: u16,
+    instruction: u16,
+    out_m: u16,
+    write_m: bool,
+    address_m: u16,
+    pc: u16,
+    d: u16,
+}
+
+// A trace of every instruction a computer runs, written the way the CPU test's output is: a row after
+// the tick, when the instruction and inM are on the CPU's inputs and D has latched its new value, and a
+// row after the tock, when A and PC have too. inM is RAM[A] as the instruction starts, and doesn't change
+// until the next one. reset is always 0, since nothing resets a running program. A computer writes one
+// once `Computer::start_trace` is called.
+//
+// Rows are written out as they are recorded rather than kept, since a run writes two for every cycle.
+// They can also be checked against a `.cmp` file as they go.
+pub struct Trace {
+    columns: Vec<Column>,
+    out: Box<dyn Write>,
+    // The first error writing the trace, after which nothing more is written
+    error: Option<io::Error>,
+    // The lines of the `.cmp` file to check the trace against
+    cmp: Option<Vec<String>>,
+    lines: usize,
+    comparison: Comparison,
+}
+
+impl Trace {
+    // A trace written to `out`, and compared with `cmp` if there is one
+    pub fn new(format: TraceFormat, out: Box<dyn Write>, cmp: Option<&str>) -> Self {
+        let columns: Vec<Column> = CPU_COLUMNS
+            .split_whitespace()
+            .filter(|spec| format == TraceFormat::Cpu || !spec.starts_with("DRegister"))
+            .map(|spec| Column::from_string(spec).unwrap())
+            .collect();
+        let cmp = cmp.map(|cmp| cmp.lines().map(str::to_string).collect());
+        let comparison = if cmp.is_some() { Comparison::Passed } else { Comparison::NotCompared };
+        let mut trace = Trace { columns, out, error: None, cmp, lines: 0, comparison };
+        let header = trace.columns.iter().map(header).collect::<String>() + "|";
+        trace.line(header);
+        trace
+    }
+
+    // Flush the output, returning the first error writing it, or else how it compared with the `.cmp` file
+    // including any lines the trace didn't reach
+    pub fn finish(&mut self) -> io::Result<Comparison> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.out.flush()?;
+        if let (Comparison::Passed, Some(cmp)) = (&self.comparison, &self.cmp) {
+            if let Some(expected) = cmp.get(self.lines) {
+                let (expected, actual) = (expected.trim_end().to_string(), String::new());
+                self.comparison = Comparison::Failed { line: self.lines + 1, expected, actual };
+            }
+        }
+        Ok(self.comparison.clone())
+    }
+
+    // Add the rows for the instruction run at `time`, which changed the registers from `before` to `after`
+    pub(crate) fn record(
+        &mut self,
+        time: u64,
+        word: u16,
+        instr: &Instr,
+        in_m: u16,
+        before: Registers,
+        after: Registers,
+    ) {
+        let (a, d, pc) = before;
+        let (new_a, new_d, new_pc) = after;
+        // outM is the ALU output, which changes as soon as A or D do
+        let (out_m, new_out_m, write_m) = match instr {
+            Instr::C(dest, comp, _) => (alu(comp, d, a, in_m), alu(comp, new_d, new_a, in_m), dest.m),
+            Instr::A(_) => (0, 0, false),
+        };
+        let tick = Row { in_m, instruction: word, out_m, write_m, address_m: a & 0x7FFF, pc, d: new_d };
+        self.row(&format!("{}+", time), tick);
+        let address_m = new_a & 0x7FFF;
+        let tock = Row { in_m, instruction: word, out_m: new_out_m, write_m, address_m, pc: new_pc, d: new_d };
+        self.row(&(time + 1).to_string(), tock);
+    }
+
+    fn row(&mut self, time: &str, row: Row) {
+        let mut line = String::new();
+        for column in &self.columns {
+            let value = match column.variable.as_str() {
+                "inM" => row.in_m,
+                "instruction" => row.instruction,
+                "outM" => row.out_m,
+                "writeM" => row.write_m as u16,
+                "addressM" => row.address_m,
+                "pc" => row.pc,
+                "DRegister[]" => row.d,
+                // time is shown as text, and reset is never set
+                _ => 0,
+            };
+            line += &cell(column, value, time);
+        }
+        self.line(line + "|");
+    }
+
+    fn line(&mut self, line: String) {
+        if let (Comparison::Passed, Some(cmp)) = (&self.comparison, &self.cmp) {
+            let expected = cmp.get(self.lines).map_or("", String::as_str);
+            if !matches(expected, &line) {
+                let expected = expected.trim_end().to_string();
+                self.comparison = Comparison::Failed { line: self.lines + 1, expected, actual: line.clone() };
+            }
+        }
+        self.lines += 1;
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.out, "{}", line) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+// Compare a trace with a `.cmp` file line by line, reporting the first line that differs or that only one
+// of them has
+pub fn compare_trace(trace: &str, cmp: &str) -> Comparison {
+    let (trace, cmp): (Vec<&str>, Vec<&str>) = (trace.lines().collect(), cmp.lines().collect());
+    for idx in 0..trace.len().max(cmp.len()) {
+        let expected = cmp.get(idx).copied().unwrap_or_default();
+        let actual = trace.get(idx).copied().unwrap_or_default();
+        if !matches(expected, actual) {
+            let (expected, actual) = (expected.trim_end().to_string(), actual.to_string());
+            return Comparison::Failed { line: idx + 1, expected, actual };
+        }
+    }
+    Comparison::Passed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, File},
+        io,
+    };
+
+    use super::{compare_trace, Trace, TraceFormat};
+    use crate::tempdir::TempDir;
+    use crate::{Comparison, Computer};
+
+    // The instructions `CPU.tst` starts with, which run the same way as a program until its inM stops
+    // being the RAM[A] that the computer would read
+    const INSTRUCTIONS: [u16; 7] = [
+        0b0011000000111001, // @12345
+        0b1110110000010000, // D=A
+        0b0101101110100000, // @23456
+        0b1110000111110000, // AD=A-D
+        0b0000001111101011, // @1003
+        0b1110001100001000, // M=D
+        0b0000001111101100, // @1004
+    ];
+
+    // Run the instructions with a trace compared against `cmp`
+    fn compare(words: &[u16], format: TraceFormat, cmp: &str) -> Comparison {
+        let mut computer = Computer::new(words).unwrap();
+        computer.start_trace(Trace::new(format, Box::new(io::sink()), Some(cmp)));
+        computer.run(words.len() as u64).unwrap();
+        computer.trace_mut().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn cpu_layout() {
+        let cmp = include_str!("../../CPU.cmp");
+        let expected: String = cmp.lines().take(13).map(|line| format!("{}\n", line)).collect();
+        assert_eq!(compare(&INSTRUCTIONS[..6], TraceFormat::Cpu, &expected), Comparison::Passed);
+        // The test leaves inM at 0 for @1004, where the computer reads the 11111 just written to RAM[1003]
+        let expected = Comparison::Failed {
+            line: 14,
+            expected: "|6+  |     0|0000001111101100|  0  |*******|   0   | 1003|    6|  11111 |".to_string(),
+            actual: "|6+  | 11111|0000001111101100|  0  |      0|   0   | 1003|    6|  11111 |".to_string(),
+        };
+        assert_eq!(compare(&INSTRUCTIONS, TraceFormat::Cpu, cmp), expected);
+        // A trace that stops short of the comparison file fails at the first line it didn't reach
+        let expected = cmp.lines().nth(11).unwrap().to_string();
+        let expected = Comparison::Failed { line: 12, expected, actual: String::new() };
+        let cmp: String = cmp.lines().take(13).map(|line| format!("{}\n", line)).collect();
+        assert_eq!(compare(&INSTRUCTIONS[..5], TraceFormat::Cpu, &cmp), expected);
+    }
+
+    #[test]
+    fn external_layout() {
+        let cmp = include_str!("../../CPU-external.cmp");
+        let temp = TempDir::new("external_layout");
+        let path = temp.0.join("trace.out");
+        let mut computer = Computer::new(&INSTRUCTIONS[..3]).unwrap();
+        computer.start_trace(Trace::new(TraceFormat::CpuExternal, Box::new(File::create(&path).unwrap()), None));
+        computer.run(3).unwrap();
+        assert_eq!(computer.trace_mut().unwrap().finish().unwrap(), Comparison::NotCompared);
+        let trace = fs::read_to_string(&path).unwrap();
+        let lines = |count| cmp.lines().take(count).map(|line| format!("{}\n", line)).collect::<String>();
+        assert_eq!(compare_trace(&trace, &lines(7)), Comparison::Passed);
+        // A trace that runs longer than the comparison file fails at the first extra line
+        let actual = trace.lines().nth(6).unwrap().to_string();
+        let expected = Comparison::Failed { line: 7, expected: String::new(), actual };
+        assert_eq!(compare_trace(&trace, &lines(6)), expected);
+        // and is reported the same way when compared as it is written
+        assert_eq!(compare(&INSTRUCTIONS[..3], TraceFormat::CpuExternal, &lines(6)), expected);
+    }
+}
@@ -0,0 +1,35 @@
+use std::{env, fs, process};
+
+fn main() {
+    // Print each file formatted, or with --write rewrite it in place. With --check nothing is written, and
+    // the files that aren't formatted are listed with a failing exit code.
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (flags, paths): (Vec<&String>, Vec<&String>) = args.iter().partition(|a| a.starts_with("--"));
+    let write = flags.iter().any(|f| *f == "--write");
+    let check = flags.iter().any(|f| *f == "--check");
+    if paths.is_empty() {
+        eprintln!("error: no files to format");
+        process::exit(1);
+    }
+
+    let mut unformatted = 0;
+    for path in paths {
+        let source = fs::read_to_string(path).unwrap();
+        let formatted = assembler::format(&source);
+        if check {
+            if formatted != source {
+                println!("{}", path);
+                unformatted += 1;
+            }
+        } else if write {
+            if formatted != source {
+                fs::write(path, formatted).unwrap();
+            }
+        } else {
+            print!("{}", formatted);
+        }
+    }
+    if unformatted > 0 {
+        process::exit(1);
+    }
+}
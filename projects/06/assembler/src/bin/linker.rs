@@ -0,0 +1,41 @@
+use std::{env, fs, path::Path, process};
+
+use assembler::Object;
+
+fn main() {
+    // Accept an output file followed by the object files to link, in the order they are placed in ROM
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (flags, paths): (Vec<&String>, Vec<&String>) = args.iter().partition(|a| a.starts_with("--"));
+    let write_symbols = flags.iter().any(|f| *f == "--symbols");
+    let (out_path, object_paths) = paths.split_first().expect("Please supply an output file as the first argument");
+    if object_paths.is_empty() {
+        eprintln!("error: no object files to link");
+        process::exit(1);
+    }
+
+    let mut objects = Vec::new();
+    for path in object_paths {
+        let text = fs::read_to_string(path).unwrap();
+        match Object::from_text(path, &text) {
+            Ok(object) => objects.push((path.to_string(), object)),
+            Err(errors) => {
+                for e in errors {
+                    eprintln!("{}\n", e);
+                }
+                process::exit(1);
+            },
+        }
+    }
+
+    let linked = assembler::link(&objects).unwrap_or_else(|errors| {
+        for e in &errors {
+            eprintln!("{}", e);
+        }
+        eprintln!("error: could not link {} due to {} previous error(s)", out_path, errors.len());
+        process::exit(1);
+    });
+    if write_symbols {
+        fs::write(Path::new(out_path).with_extension("sym"), linked.symbols.to_sym()).unwrap();
+    }
+    fs::write(out_path, linked.to_hack()).unwrap();
+}
@@ -0,0 +1,196 @@
+use std::fmt;
+
+use crate::error::AsmErrorKind;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Reg {
+    A,
+    D,
+    M,
+}
+
+impl Reg {
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            'A' => Some(Reg::A),
+            'D' => Some(Reg::D),
+            'M' => Some(Reg::M),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reg::A => write!(f, "A"),
+            Reg::D => write!(f, "D"),
+            Reg::M => write!(f, "M"),
+        }
+    }
+}
+
+// The computation part of a C-instruction. The binary operations always have D as one operand, so they
+// only hold the other one, which is A or M.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Comp {
+    Zero,
+    One,
+    MinusOne,
+    Reg(Reg),
+    Not(Reg),
+    Neg(Reg),
+    Inc(Reg),
+    Dec(Reg),
+    // D+Y
+    Add(Reg),
+    // D-Y
+    Sub(Reg),
+    // Y-D
+    SubFrom(Reg),
+    // D&Y
+    And(Reg),
+    // D|Y
+    Or(Reg),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Operand {
+    Reg(Reg),
+    One,
+}
+
+impl Comp {
+    // Whitespace is ignored and commutative operations may be written either way round, so `A+D`, `1+M`
+    // and `D | M` are all accepted and normalized to the canonical Hack spelling.
+    pub fn from_string(input: &str) -> Result<Self, AsmErrorKind> {
+        let invalid = || AsmErrorKind::InvalidComp(input.to_string());
+        let chars: Vec<char> = input.chars().filter(|c| !c.is_whitespace()).collect();
+        let operand = |c: char| match c {
+            '1' => Some(Operand::One),
+            c => Reg::from_char(c).map(Operand::Reg),
+        };
+        let comp = match chars.as_slice() {
+            ['0'] => Comp::Zero,
+            ['1'] => Comp::One,
+            ['-', '1'] => Comp::MinusOne,
+            [r] => Comp::Reg(Reg::from_char(*r).ok_or_else(invalid)?),
+            ['!', r] => Comp::Not(Reg::from_char(*r).ok_or_else(invalid)?),
+            ['-', r] => Comp::Neg(Reg::from_char(*r).ok_or_else(invalid)?),
+            [l, op, r] => {
+                let (l, r) = (operand(*l).ok_or_else(invalid)?, operand(*r).ok_or_else(invalid)?);
+                match (l, *op, r) {
+                    (Operand::Reg(x), '+', Operand::One) | (Operand::One, '+', Operand::Reg(x)) => Comp::Inc(x),
+                    (Operand::Reg(x), '-', Operand::One) => Comp::Dec(x),
+                    (Operand::Reg(l), op, Operand::Reg(r)) if l != r && (l == Reg::D || r == Reg::D) => {
+                        let (y, d_first) = if l == Reg::D { (r, true) } else { (l, false) };
+                        match (op, d_first) {
+                            ('+', _) => Comp::Add(y),
+                            ('-', true) => Comp::Sub(y),
+                            ('-', false) => Comp::SubFrom(y),
+                            ('&', _) => Comp::And(y),
+                            ('|', _) => Comp::Or(y),
+                            _ => return Err(invalid()),
+                        }
+                    },
+                    _ => return Err(invalid()),
+                }
+            },
+            _ => return Err(invalid()),
+        };
+        if comp.to_bits().is_some() {
+            Ok(comp)
+        } else {
+            Err(invalid())
+        }
+    }
+
+    // The a and c1-c6 bits, or None for a computation the ALU can't do, like `A+M` or `!1`
+    fn to_bits(self) -> Option<&'static str> {
+        let name = self.to_string();
+        COMPS.iter().find(|(c, _)| *c == name).map(|(_, bits)| *bits)
+    }
+
+    pub fn to_binary(&self) -> String {
+        self.to_bits().expect("comp is validated when constructed").to_string()
+    }
+
+    // The inverse of to_binary, from the a and c1-c6 bits
+    pub fn from_bits(bits: u16) -> Result<Self, AsmErrorKind> {
+        let bin = format!("{:07b}", bits);
+        COMPS.iter()
+            .find(|(_, b)| *b == bin)
+            .map(|(comp, _)| Comp::from_string(comp).expect("comp table entries are valid"))
+            .ok_or(AsmErrorKind::IllegalComp(bin))
+    }
+
+    // Whether the computation reads RAM[A]
+    pub fn reads_m(&self) -> bool {
+        self.reads(Reg::M)
+    }
+
+    // Whether the computation reads the value in A itself, rather than the RAM it points to
+    pub fn reads_a(&self) -> bool {
+        self.reads(Reg::A)
+    }
+
+    fn reads(&self, reg: Reg) -> bool {
+        match self {
+            Comp::Zero | Comp::One | Comp::MinusOne => false,
+            Comp::Reg(r) | Comp::Not(r) | Comp::Neg(r) | Comp::Inc(r) | Comp::Dec(r) => *r == reg,
+            Comp::Add(y) | Comp::Sub(y) | Comp::SubFrom(y) | Comp::And(y) | Comp::Or(y) => *y == reg,
+        }
+    }
+}
+
+impl fmt::Display for Comp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Comp::Zero => write!(f, "0"),
+            Comp::One => write!(f, "1"),
+            Comp::MinusOne => write!(f, "-1"),
+            Comp::Reg(r) => write!(f, "{}", r),
+            Comp::Not(r) => write!(f, "!{}", r),
+            Comp::Neg(r) => write!(f, "-{}", r),
+            Comp::Inc(r) => write!(f, "{}+1", r),
+            Comp::Dec(r) => write!(f, "{}-1", r),
+            Comp::Add(y) => write!(f, "D+{}", y),
+            Comp::Sub(y) => write!(f, "D-{}", y),
+            Comp::SubFrom(y) => write!(f, "{}-D", y),
+            Comp::And(y) => write!(f, "D&{}", y),
+            Comp::Or(y) => write!(f, "D|{}", y),
+        }
+    }
+}
+
+// The a and c1-c6 bits for each legal computation
+const COMPS: [(&str, &str); 28] = [
+    ("0",   "0101010"),
+    ("1",   "0111111"),
+    ("-1",  "0111010"),
+    ("D",   "0001100"),
+    ("A",   "0110000"),
+    ("!D",  "0001101"),
+    ("!A",  "0110001"),
+    ("-D",  "0001111"),
+    ("-A",  "0110011"),
+    ("D+1", "0011111"),
+    ("A+1", "0110111"),
+    ("D-1", "0001110"),
+    ("A-1", "0110010"),
+    ("D+A", "0000010"),
+    ("D-A", "0010011"),
+    ("A-D", "0000111"),
+    ("D&A", "0000000"),
+    ("D|A", "0010101"),
+    ("M",   "1110000"),
+    ("!M",  "1110001"),
+    ("-M",  "1110011"),
+    ("M+1", "1110111"),
+    ("M-1", "1110010"),
+    ("D+M", "1000010"),
+    ("D-M", "1010011"),
+    ("M-D", "1000111"),
+    ("D&M", "1000000"),
+    ("D|M", "1010101"),
+];
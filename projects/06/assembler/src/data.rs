@@ -0,0 +1,172 @@
+use crate::comp::{Comp, Reg};
+use crate::error::{AsmError, AsmErrorKind};
+use crate::instr::{Dest, Instr, Jump, Program, Statement, Value};
+
+// Data blocks are placed in RAM from here, and ordinary variables follow them up to the screen's memory map
+pub const DATA_START: usize = 16;
+pub const DATA_END: usize = 0x4000;
+
+// A named block of initialized RAM from `.data NAME`, and the address it was given
+#[derive(Debug, PartialEq, Clone)]
+pub struct DataBlock {
+    pub name: String,
+    pub address: usize,
+    pub len: usize,
+}
+
+// The instructions storing the initial contents, with the statement each came from
+pub type Prologue<'a> = Vec<(Instr, &'a Statement)>;
+
+// Lay out every `.data` block in RAM in the order they appear, with the `.word` and `.string` contents
+// after each one, and generate the instructions that store those contents. The instructions go at the
+// start of ROM, ahead of the program, and are attributed to the directive that asked for each word.
+pub fn layout_data(program: &[Statement]) -> Result<(Vec<DataBlock>, Prologue<'_>), Vec<AsmError>> {
+    let mut blocks: Vec<DataBlock> = Vec::new();
+    let mut prologue = Vec::new();
+    let mut errors = Vec::new();
+    let mut address = DATA_START;
+    // What D is known to hold, to avoid reloading it
+    let mut d: Option<u16> = None;
+    for statement in program {
+        match &statement.program {
+            Program::Data(name) => {
+                if blocks.iter().any(|b| &b.name == name) {
+                    errors.push(statement.error(AsmErrorKind::DuplicateSymbol(name.clone()), name));
+                }
+                blocks.push(DataBlock { name: name.clone(), address, len: 0 });
+            },
+            Program::Word(values) => {
+                let block = match blocks.last_mut() {
+                    Some(block) => block,
+                    None => {
+                        errors.push(statement.error(AsmErrorKind::WordOutsideData, statement.text.trim()));
+                        continue;
+                    },
+                };
+                for value in values {
+                    if address == DATA_END {
+                        errors.push(statement.error(AsmErrorKind::OutOfRam(block.name.clone()), statement.code()));
+                        break;
+                    }
+                    store(*value, address, &mut d, &mut |instr| prologue.push((instr, statement)));
+                    address += 1;
+                    block.len += 1;
+                }
+            },
+            _ => {},
+        }
+    }
+    if errors.is_empty() {
+        Ok((blocks, prologue))
+    } else {
+        Err(errors)
+    }
+}
+
+// The shortest sequence we know to set RAM[address] to value
+fn store(value: u16, address: usize, d: &mut Option<u16>, emit: &mut dyn FnMut(Instr)) {
+    let c = |dest: Dest, comp: Comp| Instr::C(dest, comp, Jump::new());
+    let to_d = Dest { a: false, d: true, m: false };
+    let to_m = Dest { a: false, d: false, m: true };
+    let constant = match value {
+        0 => Some(Comp::Zero),
+        1 => Some(Comp::One),
+        0xFFFF => Some(Comp::MinusOne),
+        _ => None,
+    };
+    match (constant, *d) {
+        (Some(comp), _) => {
+            emit(Instr::A(Value::Literal(address)));
+            emit(c(to_m, comp));
+            return;
+        },
+        (None, Some(held)) if held == value => {},
+        (None, Some(held)) if held.wrapping_add(1) == value => emit(c(to_d, Comp::Inc(Reg::D))),
+        (None, Some(held)) if held.wrapping_sub(1) == value => emit(c(to_d, Comp::Dec(Reg::D))),
+        // The A-register only takes 15 bits, so larger values are loaded inverted
+        _ if value & 0x8000 == 0 => {
+            emit(Instr::A(Value::Literal(value as usize)));
+            emit(c(to_d, Comp::Reg(Reg::A)));
+        },
+        _ => {
+            emit(Instr::A(Value::Literal(!value as usize)));
+            emit(c(to_d, Comp::Not(Reg::A)));
+        },
+    }
+    *d = Some(value);
+    emit(Instr::A(Value::Literal(address)));
+    emit(c(to_m, Comp::Reg(Reg::D)));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assemble, parse, AsmErrorKind, Program, SymbolKind};
+
+    #[test]
+    fn data_blocks() {
+        let source = "@i\nM=0\n.data TABLE\n.word 5, 5, 6, 0, -1, 0x8000\n.data MSG\n.string \"Hi\"\n@TABLE+1\n@MSG";
+        let assembled = assemble(source).unwrap();
+        let prologue: Vec<u16> = vec![
+            5, 0xEC10, 16, 0xE308, // @5 D=A @16 M=D
+            17, 0xE308,            // @17 M=D
+            0xE7D0, 18, 0xE308,    // D=D+1 @18 M=D
+            19, 0xEA88,            // @19 M=0
+            20, 0xEE88,            // @20 M=-1
+            0x7FFF, 0xEC50, 21, 0xE308, // @32767 D=!A @21 M=D
+            72, 0xEC10, 22, 0xE308, // 'H'
+            105, 0xEC10, 23, 0xE308, // 'i'
+            24, 0xEA88,            // terminator
+        ];
+        assert_eq!(assembled.words[..prologue.len()], prologue);
+        assert_eq!(assembled.words[prologue.len()..], [25, 0xEA88, 17, 22]);
+        assert_eq!(assembled.symbols.get("TABLE"), Some(&16));
+        assert_eq!(assembled.symbols.kind("MSG"), Some(SymbolKind::Data));
+        assert_eq!(assembled.symbols.get("i"), Some(&25));
+    }
+
+    #[test]
+    fn comment_markers_in_strings() {
+        let source = ".data URL // the address\n.string \"a//b \\\" //\" // not part of it\n.word '/' // slash";
+        let program: Vec<Program> = parse("<input>", source).unwrap().into_iter().map(|s| s.program).collect();
+        let message: Vec<u16> = "a//b \" //".bytes().map(u16::from).chain([0]).collect();
+        assert_eq!(program, vec![Program::Data("URL".to_string()), Program::Word(message), Program::Word(vec![47])]);
+        assert!(assemble(source).is_ok());
+    }
+
+    #[test]
+    fn data_fills_ram() {
+        let source = format!(".data S\n.string \"{}\"\n@S", "x".repeat(33000));
+        let errors = assemble(&source).unwrap_err();
+        assert_eq!(errors.iter().map(|e| (e.line, e.column)).collect::<Vec<_>>(), vec![(2, 1)]);
+        assert_eq!(errors[0].kind, AsmErrorKind::OutOfRam("S".to_string()));
+        // The block ends just before SCREEN, leaving no room for a variable
+        let source = format!(".data S\n.string \"{}\"\n@S\n@i", "x".repeat(16367));
+        let errors = assemble(&source).unwrap_err();
+        assert_eq!(errors.iter().map(|e| (e.line, e.kind.clone())).collect::<Vec<_>>(), vec![
+            (4, AsmErrorKind::OutOfRam("i".to_string())),
+        ]);
+        assert_eq!(assemble(&source.replace("@i", "@SCREEN")).unwrap().symbols.get("S"), Some(&16));
+    }
+
+    #[test]
+    fn data_errors() {
+        let errors = assemble(".word 1\n.data T\n.data T\n(LOOP)\n.data LOOP\n.word 70000\n.string \"é\"").unwrap_err();
+        let kinds: Vec<(usize, AsmErrorKind)> = errors.into_iter().map(|e| (e.line, e.kind)).collect();
+        assert_eq!(kinds, vec![
+            (6, AsmErrorKind::WordOutOfRange("70000".to_string())),
+            (7, AsmErrorKind::InvalidLiteral("\"é\"".to_string())),
+        ]);
+        let errors = assemble(".word 1\n.data T\n.data T").unwrap_err();
+        let kinds: Vec<(usize, AsmErrorKind)> = errors.into_iter().map(|e| (e.line, e.kind)).collect();
+        assert_eq!(kinds, vec![
+            (1, AsmErrorKind::WordOutsideData),
+            (3, AsmErrorKind::DuplicateSymbol("T".to_string())),
+        ]);
+        let errors = assemble("(LOOP)\n.data LOOP\n.data SCREEN").unwrap_err();
+        let kinds: Vec<(usize, AsmErrorKind)> = errors.into_iter().map(|e| (e.line, e.kind)).collect();
+        assert_eq!(kinds, vec![
+            (2, AsmErrorKind::DuplicateSymbol("LOOP".to_string())),
+            (3, AsmErrorKind::DuplicateSymbol("SCREEN".to_string())),
+        ]);
+    }
+}
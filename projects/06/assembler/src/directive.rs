@@ -0,0 +1,101 @@
+use crate::error::{AsmError, AsmErrorKind};
+use crate::instr::{is_symbol, parse_unsigned};
+
+// Lines beginning with '.' are directives to the assembler rather than instructions
+#[derive(Debug, PartialEq, Clone)]
+pub enum Directive {
+    Include(String),
+    // `.macro NAME param, param`
+    Macro(String, Vec<String>),
+    Endm,
+    Export(String),
+    Import(String),
+    Data(String),
+    // The words from either `.word` or `.string`
+    Word(Vec<u16>),
+}
+
+impl Directive {
+    pub fn from_string(input: &str) -> Result<Self, AsmError> {
+        let (name, args) = match input.split_once(char::is_whitespace) {
+            Some((name, args)) => (name, args.trim()),
+            None => (input, ""),
+        };
+        let args_col = input.len() - args.len();
+        match name {
+            ".include" => {
+                let file = args
+                    .strip_prefix('"')
+                    .and_then(|a| a.strip_suffix('"'))
+                    .filter(|f| !f.is_empty())
+                    .ok_or_else(|| AsmError::new(AsmErrorKind::InvalidDirective(input.to_string()), args_col, args))?;
+                Ok(Directive::Include(file.to_string()))
+            },
+            ".macro" => {
+                let invalid = || AsmError::new(AsmErrorKind::InvalidDirective(input.to_string()), args_col, args);
+                let mut names = args.split(|c: char| c == ',' || c.is_whitespace()).filter(|a| !a.is_empty());
+                let name = names.next().filter(|n| is_symbol(n)).ok_or_else(invalid)?;
+                let params: Vec<String> = names.map(str::to_string).collect();
+                if params.iter().any(|p| !is_symbol(p)) {
+                    return Err(invalid());
+                }
+                Ok(Directive::Macro(name.to_string(), params))
+            },
+            ".endm" if args.is_empty() => Ok(Directive::Endm),
+            // Local labels are private to their scope, let alone their module
+            ".export" if is_symbol(args) && !args.starts_with('.') => Ok(Directive::Export(args.to_string())),
+            ".import" if is_symbol(args) && !args.starts_with('.') => Ok(Directive::Import(args.to_string())),
+            ".export" | ".import" => {
+                Err(AsmError::new(AsmErrorKind::InvalidDirective(input.to_string()), args_col, args))
+            },
+            ".data" if is_symbol(args) && !args.starts_with('.') => Ok(Directive::Data(args.to_string())),
+            ".data" => Err(AsmError::new(AsmErrorKind::InvalidDirective(input.to_string()), args_col, args)),
+            ".word" => {
+                let mut words = Vec::new();
+                for value in args.split(',').map(str::trim) {
+                    let column = args_col + value.as_ptr() as usize - args.as_ptr() as usize;
+                    words.push(parse_word(value).map_err(|kind| AsmError::new(kind, column, value))?);
+                }
+                Ok(Directive::Word(words))
+            },
+            ".string" => parse_string(args)
+                .map(Directive::Word)
+                .ok_or_else(|| AsmError::new(AsmErrorKind::InvalidLiteral(args.to_string()), args_col, args)),
+            _ => Err(AsmError::new(AsmErrorKind::UnknownDirective(name.to_string()), 0, name)),
+        }
+    }
+}
+
+// A data word is any 16 bit value: a number as written in an A-instruction up to 0xFFFF, or a negative
+// number down to -32768 stored in two's complement
+fn parse_word(input: &str) -> Result<u16, AsmErrorKind> {
+    let (negative, digits) = match input.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, input),
+    };
+    let out_of_range = || AsmErrorKind::WordOutOfRange(input.to_string());
+    let n = parse_unsigned(digits).ok_or_else(|| AsmErrorKind::InvalidLiteral(input.to_string()))??;
+    match (negative, n) {
+        (false, n) if n <= 0xFFFF => Ok(n as u16),
+        (true, n) if n <= 0x8000 => Ok((n as u16).wrapping_neg()),
+        _ => Err(out_of_range()),
+    }
+}
+
+// A double quoted string of printable ASCII, where `\"` and `\\` escape a quote and a backslash. It is
+// stored one character per word followed by a 0.
+fn parse_string(input: &str) -> Option<Vec<u16>> {
+    let mut chars = input.strip_prefix('"')?.strip_suffix('"')?.chars();
+    let mut words = Vec::new();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => chars.next().filter(|c| *c == '"' || *c == '\\')?,
+            '"' => return None,
+            c if (' '..='~').contains(&c) => c,
+            _ => return None,
+        };
+        words.push(c as u16);
+    }
+    words.push(0);
+    Some(words)
+}
@@ -0,0 +1,142 @@
+use std::collections::BTreeSet;
+
+use crate::error::{AsmError, AsmErrorKind};
+use crate::instr::{Instr, Program, Value};
+
+// Read the text `.hack` format: one instruction per line written as 16 binary digits. Blank lines are
+// skipped so that a trailing newline doesn't produce an extra word.
+pub fn parse_hack(file: &str, source: &str) -> Result<Vec<u16>, Vec<AsmError>> {
+    parse_hack_lines(file, source).map(|words| words.into_iter().map(|(_, word)| word).collect())
+}
+
+// parse_hack, keeping the 1-based line each word was read from
+pub fn parse_hack_lines(file: &str, source: &str) -> Result<Vec<(usize, u16)>, Vec<AsmError>> {
+    let mut words = Vec::new();
+    let mut errors = Vec::new();
+    for (line_idx, line) in source.lines().enumerate() {
+        let trimmed_line = line.trim();
+        if trimmed_line.is_empty() {
+            continue;
+        }
+        let column = line.len() - line.trim_start().len() + 1;
+        let is_word = trimmed_line.len() == 16 && trimmed_line.chars().all(|c| c == '0' || c == '1');
+        match u16::from_str_radix(trimmed_line, 2) {
+            Ok(word) if is_word => words.push((line_idx + 1, word)),
+            _ => errors.push(
+                AsmError::new(AsmErrorKind::InvalidWord(trimmed_line.to_string()), 0, trimmed_line)
+                    .at(file, line_idx + 1, column, line),
+            ),
+        }
+    }
+    if errors.is_empty() {
+        Ok(words)
+    } else {
+        Err(errors)
+    }
+}
+
+// Turn machine words back into a Program. Any A-instruction that loads the target of the jump that
+// follows it is given a synthesized `L<addr>` label, so control flow reads like hand-written code while
+// every other A-instruction stays a literal. Assembling the result gives back exactly the same words.
+// Each word comes with the line of `file` it was read from, which errors are reported against.
+pub fn disassemble(file: &str, words: &[(usize, u16)]) -> Result<Vec<Program>, Vec<AsmError>> {
+    let mut instrs = Vec::new();
+    let mut errors = Vec::new();
+    for (line, word) in words {
+        let bin = format!("{:016b}", word);
+        match Instr::from_word(*word) {
+            Ok(instr) => instrs.push(instr),
+            Err(kind) => {
+                let (column, len) = match kind {
+                    AsmErrorKind::IllegalComp(_) => (3, 7),
+                    _ => (0, 3),
+                };
+                let err = AsmError::new(kind, column, &bin[column..column + len]);
+                errors.push(err.at(file, *line, 1, &bin));
+            },
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // Labels may point one past the last instruction, as with a label at the end of a file
+    let targets: BTreeSet<usize> = instrs.windows(2).filter_map(|pair| match pair {
+        [Instr::A(Value::Literal(addr)), Instr::C(_, _, jump)] if jump.is_jump() && *addr <= instrs.len() => Some(*addr),
+        _ => None,
+    }).collect();
+    let label = |addr: usize| format!("L{}", addr);
+
+    let mut program = Vec::new();
+    let mut next_instr = instrs.iter().skip(1);
+    for (addr, instr) in instrs.iter().enumerate() {
+        if targets.contains(&addr) {
+            program.push(Program::Label(label(addr)));
+        }
+        let jumps_next = matches!(next_instr.next(), Some(Instr::C(_, _, jump)) if jump.is_jump());
+        let instr = match instr {
+            Instr::A(Value::Literal(target)) if jumps_next && targets.contains(target) => {
+                Instr::A(Value::Variable(label(*target)))
+            },
+            _ => instr.clone(),
+        };
+        program.push(Program::Instr(instr));
+    }
+    if targets.contains(&instrs.len()) {
+        program.push(Program::Label(label(instrs.len())));
+    }
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble, parse_hack, parse_hack_lines};
+    use crate::{assemble, AsmErrorKind};
+
+    // Assembled words, numbered as if they were read from a `.hack` file
+    fn lines(words: &[u16]) -> Vec<(usize, u16)> {
+        words.iter().enumerate().map(|(addr, word)| (addr + 1, *word)).collect()
+    }
+
+    fn round_trip(source: &str) {
+        let words = assemble(source).unwrap().words;
+        let program = disassemble("<words>", &lines(&words)).unwrap();
+        let text: String = program.iter().map(|p| format!("{}\n", p)).collect();
+        assert_eq!(assemble(&text).unwrap().words, words);
+    }
+
+    #[test]
+    fn disassemble_labels() {
+        let words = assemble("@5\nD=M;JGT\n(LOOP)\n@2\n0;JMP\n@2\nD=A").unwrap().words;
+        let text: Vec<String> = disassemble("<words>", &lines(&words)).unwrap().iter().map(|p| p.to_string()).collect();
+        assert_eq!(text, vec!["@L5", "D=M;JGT", "(L2)", "@L2", "0;JMP", "@2", "(L5)", "D=A"]);
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(include_str!("../../max/Max.asm"));
+        round_trip(include_str!("../../rect/Rect.asm"));
+        round_trip(include_str!("../../pong/Pong.asm"));
+    }
+
+    #[test]
+    fn illegal_words() {
+        let words = parse_hack_lines("Bad.hack", "0000000000000001\n1111111111010000\n1010101010000000\n").unwrap();
+        let errors = disassemble("Bad.hack", &words).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!((errors[0].line, errors[0].column), (2, 4));
+        assert_eq!(errors[0].kind, AsmErrorKind::IllegalComp("1111111".to_string()));
+        assert_eq!(errors[1].kind, AsmErrorKind::ReservedBits("101".to_string()));
+        assert!(parse_hack("Bad.hack", "10101\n").is_err());
+    }
+
+    #[test]
+    fn blank_lines() {
+        let source = "0000000000000001\n\n\n1111111111010000\n";
+        assert_eq!(parse_hack("Bad.hack", source).unwrap(), vec![1, 0xFFD0]);
+        let errors = disassemble("Bad.hack", &parse_hack_lines("Bad.hack", source).unwrap()).unwrap_err();
+        assert_eq!((errors[0].line, errors[0].column), (4, 4));
+        let errors = parse_hack("Bad.hack", "0000000000000001\n\n10101\n").unwrap_err();
+        assert_eq!(errors[0].line, 3);
+    }
+}
@@ -0,0 +1,156 @@
+use std::{fmt, io};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum AsmErrorKind {
+    EmptyInstruction,
+    UnterminatedLabel,
+    EmptyLabel,
+    EmptyValue,
+    InvalidSymbol(String),
+    InvalidDest(String),
+    InvalidComp(String),
+    InvalidJump(String),
+    InvalidWord(String),
+    ReservedBits(String),
+    IllegalComp(String),
+    InvalidExpression(String),
+    UndefinedSymbol(String),
+    ValueOutOfRange(String),
+    InvalidLiteral(String),
+    NegativeExpression(String),
+    UnknownDirective(String),
+    InvalidDirective(String),
+    IncludeFailed(String, io::ErrorKind),
+    IncludeCycle(String),
+    UnterminatedMacro(String),
+    DuplicateMacro(String),
+    NestedMacro(String),
+    UnexpectedEndm,
+    MacroArguments(usize, usize),
+    UnknownMacroParameter(String),
+    MacroRecursion(String),
+    NotRelocatable(String),
+    InvalidObject(String),
+    InvalidSymbolLine(String),
+    // The global label whose scope the local label is defined in
+    LocalLabelOutOfScope(String),
+    DuplicateSymbol(String),
+    WordOutOfRange(String),
+    // The number of words in the program
+    ProgramTooLarge(usize),
+    // The data block or variable that would reach SCREEN
+    OutOfRam(String),
+    WordOutsideData,
+    DataInObject,
+}
+
+impl fmt::Display for AsmErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsmErrorKind::EmptyInstruction => write!(f, "expected an instruction"),
+            AsmErrorKind::UnterminatedLabel => write!(f, "label declaration is missing a closing ')'"),
+            AsmErrorKind::EmptyLabel => write!(f, "label declaration has no name"),
+            AsmErrorKind::EmptyValue => write!(f, "A-instruction is missing a value after '@'"),
+            AsmErrorKind::InvalidSymbol(s) => write!(f, "invalid symbol {:?}", s),
+            AsmErrorKind::InvalidDest(d) => write!(f, "invalid destination {:?}", d),
+            AsmErrorKind::InvalidComp(c) => write!(f, "invalid computation {:?}", c),
+            AsmErrorKind::InvalidJump(j) => write!(f, "invalid jump {:?}", j),
+            AsmErrorKind::InvalidWord(w) => write!(f, "expected 16 binary digits, found {:?}", w),
+            AsmErrorKind::ReservedBits(b) => write!(f, "C-instruction must begin with 111, found {}", b),
+            AsmErrorKind::IllegalComp(c) => write!(f, "comp bits {} are not a legal Hack computation", c),
+            AsmErrorKind::InvalidExpression(e) => write!(f, "invalid expression: {}", e),
+            AsmErrorKind::UndefinedSymbol(s) => {
+                write!(f, "{:?} is not a label or a variable allocated before this instruction", s)
+            },
+            AsmErrorKind::ValueOutOfRange(v) => write!(f, "value {} does not fit in 15 bits (0..=32767)", v),
+            AsmErrorKind::InvalidLiteral(l) => write!(f, "invalid literal {:?}", l),
+            AsmErrorKind::NegativeExpression(e) => {
+                write!(f, "@- only negates a single number or symbol, found {:?}", e)
+            },
+            AsmErrorKind::UnknownDirective(d) => write!(f, "unknown directive {:?}", d),
+            AsmErrorKind::InvalidDirective(d) => write!(f, "malformed directive {:?}", d),
+            AsmErrorKind::IncludeFailed(file, e) => write!(f, "could not include {:?}: {}", file, e),
+            AsmErrorKind::IncludeCycle(file) => write!(f, "{:?} includes itself", file),
+            AsmErrorKind::UnterminatedMacro(m) => write!(f, "macro {:?} is missing its .endm", m),
+            AsmErrorKind::DuplicateMacro(m) => write!(f, "macro {:?} is already defined", m),
+            AsmErrorKind::NestedMacro(m) => write!(f, "macro {:?} can't be defined inside another macro", m),
+            AsmErrorKind::UnexpectedEndm => write!(f, ".endm without a matching .macro"),
+            AsmErrorKind::MacroArguments(expected, found) => {
+                write!(f, "macro takes {} argument(s) but {} were given", expected, found)
+            },
+            AsmErrorKind::UnknownMacroParameter(p) => write!(f, "macro has no parameter {:?}", p),
+            AsmErrorKind::MacroRecursion(m) => write!(f, "macro {:?} expands recursively", m),
+            AsmErrorKind::NotRelocatable(e) => {
+                write!(f, "{} can't be relocated, expressions using labels must be label + constant", e)
+            },
+            AsmErrorKind::InvalidObject(l) => write!(f, "invalid object file line {:?}", l),
+            AsmErrorKind::InvalidSymbolLine(l) => write!(f, "expected a `kind name address` line, found {:?}", l),
+            AsmErrorKind::LocalLabelOutOfScope(scope) if scope.is_empty() => {
+                write!(f, "local label is only visible before the first global label")
+            },
+            AsmErrorKind::LocalLabelOutOfScope(scope) => {
+                write!(f, "local label is only visible after {:?} and before the next global label", scope)
+            },
+            AsmErrorKind::DuplicateSymbol(s) => write!(f, "{:?} is already defined", s),
+            AsmErrorKind::WordOutOfRange(w) => write!(f, "word {} does not fit in 16 bits (-32768..=65535)", w),
+            AsmErrorKind::ProgramTooLarge(len) => write!(f, "program is {} words long but ROM holds 32768", len),
+            AsmErrorKind::OutOfRam(s) => write!(f, "{:?} does not fit in RAM below SCREEN", s),
+            AsmErrorKind::WordOutsideData => write!(f, "data must follow a .data directive naming its block"),
+            AsmErrorKind::DataInObject => write!(f, "data directives can't be used when assembling an object file"),
+        }
+    }
+}
+
+// A diagnostic pointing at the source text that could not be assembled. Parsing functions only know
+// about the single instruction they are handed, so they produce errors with a 0-based column relative to
+// that instruction and the caller fills in the file, line and 1-based column in the line with `at`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct AsmError {
+    pub kind: AsmErrorKind,
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+    pub source_line: String,
+}
+
+impl AsmError {
+    pub fn new(kind: AsmErrorKind, column: usize, text: &str) -> Self {
+        AsmError {
+            kind,
+            file: String::new(),
+            line: 0,
+            column,
+            text: text.to_string(),
+            source_line: String::new(),
+        }
+    }
+
+    pub fn at(mut self, file: &str, line: usize, column_offset: usize, source_line: &str) -> Self {
+        self.file = file.to_string();
+        self.line = line;
+        self.column += column_offset;
+        self.source_line = source_line.to_string();
+        self
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // file:line:col: error: message
+        //    |
+        // 12 | D;JMPP
+        //    |   ^^^^
+        writeln!(f, "{}:{}:{}: error: {}", self.file, self.line, self.column, self.kind)?;
+        if self.source_line.is_empty() {
+            return Ok(());
+        }
+        let gutter = " ".repeat(self.line.to_string().len());
+        let underline = "^".repeat(self.text.chars().count().max(1));
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.source_line)?;
+        write!(f, "{} | {}{}", gutter, " ".repeat(self.column.saturating_sub(1)), underline)
+    }
+}
+
+impl std::error::Error for AsmError {}
@@ -0,0 +1,301 @@
+use std::fmt;
+
+use crate::error::AsmErrorKind;
+use crate::instr::{is_symbol, parse_number, MAX_LITERAL};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    And,
+    Or,
+}
+
+impl Op {
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            '+' => Some(Op::Add),
+            '-' => Some(Op::Sub),
+            '*' => Some(Op::Mul),
+            '/' => Some(Op::Div),
+            '&' => Some(Op::And),
+            '|' => Some(Op::Or),
+            _ => None,
+        }
+    }
+
+    // Higher binds tighter: * and / over + and -, over &, over |
+    fn precedence(&self) -> usize {
+        match self {
+            Op::Or => 0,
+            Op::And => 1,
+            Op::Add | Op::Sub => 2,
+            Op::Mul | Op::Div => 3,
+        }
+    }
+
+    fn apply(&self, l: i64, r: i64) -> Option<i64> {
+        match self {
+            Op::Add => l.checked_add(r),
+            Op::Sub => l.checked_sub(r),
+            Op::Mul => l.checked_mul(r),
+            Op::Div => l.checked_div(r),
+            Op::And => Some(l & r),
+            Op::Or => Some(l | r),
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let c = match self {
+            Op::Add => '+',
+            Op::Sub => '-',
+            Op::Mul => '*',
+            Op::Div => '/',
+            Op::And => '&',
+            Op::Or => '|',
+        };
+        write!(f, "{}", c)
+    }
+}
+
+// A constant expression in an A-instruction, such as `@SCREEN+32*5`. Symbols are looked up once every
+// label is known, so expressions can refer to labels defined further down the file.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr {
+    Literal(usize),
+    Symbol(String),
+    Binary(Box<Expr>, Op, Box<Expr>),
+}
+
+impl Expr {
+    pub fn from_string(input: &str) -> Result<Self, AsmErrorKind> {
+        let tokens = tokenize(input)?;
+        let mut pos = 0;
+        let expr = parse_binary(input, &tokens, &mut pos, 0)?;
+        match tokens.get(pos) {
+            None => Ok(expr),
+            Some(t) => Err(AsmErrorKind::InvalidExpression(format!("unexpected {:?} in {:?}", t.to_string(), input))),
+        }
+    }
+
+    // Evaluate with `lookup` resolving symbols. The result must fit in the 15 bits of an A-instruction,
+    // but intermediate values may go outside that range.
+    pub fn eval(&self, lookup: &dyn Fn(&str) -> Option<usize>) -> Result<usize, AsmErrorKind> {
+        let value = self.eval_signed(lookup)?;
+        if (0..=MAX_LITERAL as i64).contains(&value) {
+            Ok(value as usize)
+        } else {
+            Err(AsmErrorKind::ValueOutOfRange(value.to_string()))
+        }
+    }
+
+    fn eval_signed(&self, lookup: &dyn Fn(&str) -> Option<usize>) -> Result<i64, AsmErrorKind> {
+        match self {
+            Expr::Literal(l) => Ok(*l as i64),
+            Expr::Symbol(s) => lookup(s).map(|v| v as i64).ok_or_else(|| AsmErrorKind::UndefinedSymbol(s.clone())),
+            Expr::Binary(l, op, r) => {
+                let (l, r) = (l.eval_signed(lookup)?, r.eval_signed(lookup)?);
+                if *op == Op::Div && r == 0 {
+                    return Err(AsmErrorKind::InvalidExpression(format!("division by zero in {}", self)));
+                }
+                op.apply(l, r).ok_or_else(|| AsmErrorKind::ValueOutOfRange(self.to_string()))
+            },
+        }
+    }
+
+    // Every symbol the expression refers to, left to right
+    pub fn symbols(&self) -> Vec<&str> {
+        match self {
+            Expr::Literal(_) => vec![],
+            Expr::Symbol(s) => vec![s.as_str()],
+            Expr::Binary(l, _, r) => {
+                let mut symbols = l.symbols();
+                symbols.append(&mut r.symbols());
+                symbols
+            },
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Literal(l) => write!(f, "{}", l),
+            Expr::Symbol(s) => write!(f, "{}", s),
+            Expr::Binary(l, op, r) => {
+                // Only parenthesize where precedence or left associativity requires it
+                let needs_parens = |e: &Expr, right: bool| match e {
+                    Expr::Binary(_, inner, _) => {
+                        inner.precedence() < op.precedence() || (right && inner.precedence() == op.precedence())
+                    },
+                    _ => false,
+                };
+                for (e, right) in [(l, false), (r, true)] {
+                    if right {
+                        write!(f, "{}", op)?;
+                    }
+                    if needs_parens(e, right) {
+                        write!(f, "({})", e)?;
+                    } else {
+                        write!(f, "{}", e)?;
+                    }
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Number(usize),
+    Symbol(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Symbol(s) => write!(f, "{}", s),
+            Token::Op(op) => write!(f, "{}", op),
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, AsmErrorKind> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '\'' => {
+                // Character literals may contain operators, so take the whole quoted character
+                let end = chars.nth(1).map(|(i, _)| i + 1).unwrap_or(input.len());
+                match parse_number(&input[start..end]) {
+                    Some(n) => Token::Number(n?),
+                    None => return Err(AsmErrorKind::InvalidLiteral(input[start..end].to_string())),
+                }
+            },
+            c if c.is_whitespace() => continue,
+            c => match Op::from_char(c) {
+                Some(op) => Token::Op(op),
+                None => {
+                    // Numbers and symbols run until the next operator, parenthesis or space
+                    let mut end = start + c.len_utf8();
+                    while let Some((i, c)) = chars.peek() {
+                        if Op::from_char(*c).is_some() || "()".contains(*c) || c.is_whitespace() {
+                            break;
+                        }
+                        end = i + c.len_utf8();
+                        chars.next();
+                    }
+                    let word = &input[start..end];
+                    if let Some(n) = parse_number(word) {
+                        Token::Number(n?)
+                    } else if is_symbol(word) {
+                        Token::Symbol(word.to_string())
+                    } else {
+                        return Err(AsmErrorKind::InvalidSymbol(word.to_string()));
+                    }
+                },
+            },
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+// Precedence climbing: parse operands joined by operators that bind at least as tightly as `min_prec`
+fn parse_binary(input: &str, tokens: &[Token], pos: &mut usize, min_prec: usize) -> Result<Expr, AsmErrorKind> {
+    let mut lhs = parse_primary(input, tokens, pos)?;
+    while let Some(Token::Op(op)) = tokens.get(*pos) {
+        if op.precedence() < min_prec {
+            break;
+        }
+        *pos += 1;
+        let rhs = parse_binary(input, tokens, pos, op.precedence() + 1)?;
+        lhs = Expr::Binary(Box::new(lhs), *op, Box::new(rhs));
+    }
+    Ok(lhs)
+}
+
+fn parse_primary(input: &str, tokens: &[Token], pos: &mut usize) -> Result<Expr, AsmErrorKind> {
+    let token = tokens.get(*pos).ok_or_else(|| {
+        AsmErrorKind::InvalidExpression(format!("expression {:?} ends unexpectedly", input))
+    })?;
+    *pos += 1;
+    match token {
+        Token::Number(n) => Ok(Expr::Literal(*n)),
+        Token::Symbol(s) => Ok(Expr::Symbol(s.clone())),
+        Token::Open => {
+            let expr = parse_binary(input, tokens, pos, 0)?;
+            match tokens.get(*pos) {
+                Some(Token::Close) => {
+                    *pos += 1;
+                    Ok(expr)
+                },
+                _ => Err(AsmErrorKind::InvalidExpression(format!("missing ')' in {:?}", input))),
+            }
+        },
+        t => Err(AsmErrorKind::InvalidExpression(format!("unexpected {:?} in {:?}", t.to_string(), input))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Expr;
+    use crate::AsmErrorKind;
+
+    fn eval(input: &str) -> Result<usize, AsmErrorKind> {
+        let lookup = |s: &str| match s {
+            "SCREEN" => Some(16384),
+            "KBD" => Some(24576),
+            "LOOP" => Some(10),
+            _ => None,
+        };
+        Expr::from_string(input)?.eval(&lookup)
+    }
+
+    #[test]
+    fn precedence_and_parens() {
+        assert_eq!(eval("SCREEN+32*5"), Ok(16544));
+        assert_eq!(eval("(SCREEN+32)*1"), Ok(16416));
+        assert_eq!(eval("KBD-1"), Ok(24575));
+        assert_eq!(eval("LOOP+2"), Ok(12));
+        assert_eq!(eval("10-4-3"), Ok(3));
+        assert_eq!(eval("100/7/2"), Ok(7));
+        assert_eq!(eval("12&10|1"), Ok(9));
+        assert_eq!(eval("12 & (10|1)"), Ok(8));
+        assert_eq!(eval("SCREEN+0x20"), Ok(16416));
+        assert_eq!(eval("'+'+0b1"), Ok(44));
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for input in ["SCREEN+32*5", "(SCREEN+32)*5", "10-(4-3)", "10-4-3", "1|2&3", "(1|2)&3"] {
+            assert_eq!(Expr::from_string(input).unwrap().to_string(), input);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(eval("KBD*2"), Err(AsmErrorKind::ValueOutOfRange("49152".to_string())));
+        assert_eq!(eval("LOOP-11"), Err(AsmErrorKind::ValueOutOfRange("-1".to_string())));
+        assert_eq!(eval("i+1"), Err(AsmErrorKind::UndefinedSymbol("i".to_string())));
+        assert!(matches!(eval("LOOP/0"), Err(AsmErrorKind::InvalidExpression(_))));
+        assert!(matches!(eval("(LOOP+1"), Err(AsmErrorKind::InvalidExpression(_))));
+        assert!(matches!(eval("LOOP+"), Err(AsmErrorKind::InvalidExpression(_))));
+        assert!(matches!(eval("LOOP 1"), Err(AsmErrorKind::InvalidExpression(_))));
+        assert_eq!(eval("2x+1"), Err(AsmErrorKind::InvalidSymbol("2x".to_string())));
+    }
+}
@@ -0,0 +1,87 @@
+use crate::instr::{Instr, Program};
+use crate::parse::parse_verbatim;
+
+const INDENT: &str = "    ";
+
+// Rewrite assembly source in the canonical style: labels and directives flush left, instructions
+// indented, and C-instructions spelled the way the disassembler prints them. Comments are kept, with
+// the ones after code aligned within each blank line separated paragraph. A-instructions keep their
+// value as written, so `@0x4000` and `@'A'` stay readable, and lines that aren't instructions on their
+// own (like macro invocations) are kept as they are. Runs of blank lines are collapsed into one.
+pub fn format(source: &str) -> String {
+    let statements = parse_verbatim("<input>", source);
+    // Each line's code, indented, and its comment. Blank lines are empty with no comment.
+    let mut lines: Vec<(String, Option<&str>)> = Vec::new();
+    for statement in &statements {
+        let code = statement.code();
+        let comment = statement.comment.as_deref().map(str::trim_end);
+        let formatted = match &statement.program {
+            Program::Instr(instr @ Instr::C(..)) => format!("{}{}", INDENT, instr),
+            // Comments on their own line stay indented if they were, to keep commenting on the code
+            _ if code.is_empty() && comment.is_some() && statement.text.starts_with(char::is_whitespace) => {
+                INDENT.to_string()
+            },
+            _ if code.is_empty() || code.starts_with('(') || code.starts_with('.') => code.to_string(),
+            _ => format!("{}{}", INDENT, code),
+        };
+        let blank = formatted.is_empty() && comment.is_none();
+        if blank && lines.last().is_none_or(|(code, comment)| code.is_empty() && comment.is_none()) {
+            continue;
+        }
+        lines.push((formatted, comment));
+    }
+    if lines.last().is_some_and(|(code, comment)| code.is_empty() && comment.is_none()) {
+        lines.pop();
+    }
+
+    let mut paragraphs = Vec::new();
+    for paragraph in lines.split(|(code, comment)| code.is_empty() && comment.is_none()) {
+        let column = paragraph
+            .iter()
+            .filter(|(code, comment)| comment.is_some() && !code.trim().is_empty())
+            .map(|(code, _)| code.chars().count())
+            .max()
+            .unwrap_or(0);
+        let mut out = String::new();
+        for (code, comment) in paragraph {
+            match comment {
+                Some(comment) if !code.trim().is_empty() => out += &format!("{:<2$}  //{}\n", code, comment, column),
+                Some(comment) => out += &format!("{}//{}\n", code, comment),
+                None => out += &format!("{}\n", code),
+            }
+        }
+        paragraphs.push(out);
+    }
+    paragraphs.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format;
+
+    #[test]
+    fn format_source() {
+        let source = "// Header\n\n\n(LOOP)   // top\n  @R0 // load\nM = M + 1\n MD=M|D;JGT  //  both\n   // note\n\n.data MSG\n.string \"a//b\" // text\nPUSH 1,  2\n\n";
+        assert_eq!(format(source), concat!(
+            "// Header\n",
+            "\n",
+            "(LOOP)          // top\n",
+            "    @R0         // load\n",
+            "    M=M+1\n",
+            "    MD=D|M;JGT  //  both\n",
+            "    // note\n",
+            "\n",
+            ".data MSG\n",
+            ".string \"a//b\"  // text\n",
+            "    PUSH 1,  2\n",
+        ));
+    }
+
+    #[test]
+    fn format_is_idempotent() {
+        let source = include_str!("../../max/Max.asm");
+        let formatted = format(source);
+        assert_eq!(format(&formatted), formatted);
+        assert!(formatted.contains("\n    D=D-M  // D = first number - second number\n"));
+    }
+}
@@ -0,0 +1,529 @@
+use std::fmt;
+
+use crate::comp::{Comp, Reg};
+use crate::error::{AsmError, AsmErrorKind};
+use crate::expr::Expr;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Program {
+    Label(String),
+    Instr(Instr),
+    // Makes a label visible to other modules when assembling an object file
+    Export(String),
+    // Names a label that another module exports, when assembling an object file
+    Import(String),
+    // Starts a named block of initialized RAM
+    Data(String),
+    // Words appended to the current data block
+    Word(Vec<u16>),
+    // A line kept as written, when source is parsed verbatim: a blank line, a comment, an include, a
+    // macro definition or invocation, or anything else that isn't a single statement
+    Source(String),
+}
+
+// A Program item together with where it came from, so that later passes can still point at the source
+#[derive(Debug, PartialEq, Clone)]
+pub struct Statement {
+    pub program: Program,
+    pub file: String,
+    pub line: usize,
+    pub text: String,
+    // What followed `//` on the line, if the statement was written there rather than expanded from a macro
+    pub comment: Option<String>,
+}
+
+impl Statement {
+    // The code on the statement's line, without its comment
+    pub fn code(&self) -> &str {
+        let code = match &self.comment {
+            Some(comment) => self.text.strip_suffix(comment.as_str()).and_then(|t| t.strip_suffix("//")),
+            None => None,
+        };
+        code.unwrap_or(&self.text).trim()
+    }
+
+    // An error pointing at the first occurrence of `text` in this statement's line
+    pub fn error(&self, kind: AsmErrorKind, text: &str) -> AsmError {
+        let column = self.text.find(text).unwrap_or(0);
+        AsmError::new(kind, 0, text).at(&self.file, self.line, column + 1, &self.text)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Instr {
+    A(Value),
+    C(Dest, Comp, Jump),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Dest {
+    pub a: bool,
+    pub d: bool,
+    pub m: bool,
+}
+
+impl Default for Dest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dest {
+    pub fn new() -> Self {
+        Self { a: false, m: false, d: false }
+    }
+    
+    // The registers in the order the book gives them (AMD), or in the order of the 2nd edition tools (ADM)
+    pub fn from_string(input: &str) -> Result<Self, AsmErrorKind> {
+        let (a, d, m) = match input {
+            "M" => (false, false, true),
+            "D" => (false, true, false),
+            "MD" | "DM" => (false, true, true),
+            "A" => (true, false, false),
+            "AM" => (true, false, true),
+            "AD" => (true, true, false),
+            "AMD" | "ADM" => (true, true, true),
+            _ => return Err(AsmErrorKind::InvalidDest(input.to_string())),
+        };
+        Ok(Dest{a, d, m})
+    }
+
+    pub fn to_binary(&self) -> String {
+       format!("{}{}{}", self.a as usize, self.d as usize, self.m as usize) 
+    }
+
+    // The inverse of to_binary, from the d1 d2 d3 bits
+    pub fn from_bits(bits: u16) -> Self {
+        Dest { a: bits & 0b100 != 0, d: bits & 0b010 != 0, m: bits & 0b001 != 0 }
+    }
+}
+
+impl fmt::Display for Dest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (set, name) in [(self.a, "A"), (self.m, "M"), (self.d, "D")] {
+            if set {
+                write!(f, "{}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Jump {
+    pub lt: bool,
+    pub eq: bool,
+    pub gt: bool,
+}
+
+impl Default for Jump {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Jump {
+    pub fn new() -> Self {
+        Self { lt: false, eq: false, gt: false }
+    }
+
+    pub fn from_string(input: &str) -> Result<Self, AsmErrorKind> {
+        let (lt, eq, gt) =   match input {
+            "JGT" => (false, false, true),
+            "JEQ" => (false, true, false),
+            "JGE" => (false, true, true),
+            "JLT" => (true, false, false),
+            "JNE" => (true, false, true),
+            "JLE" => (true, true, false),
+            "JMP" => (true, true, true),
+            _ => return Err(AsmErrorKind::InvalidJump(input.to_string())),
+        };
+        Ok(Jump{lt, eq, gt})
+    }
+
+    pub fn to_binary(&self) -> String {
+       format!("{}{}{}", self.lt as usize, self.eq as usize, self.gt as usize) 
+    }
+
+    // The inverse of to_binary, from the j1 j2 j3 bits
+    pub fn from_bits(bits: u16) -> Self {
+        Jump { lt: bits & 0b100 != 0, eq: bits & 0b010 != 0, gt: bits & 0b001 != 0 }
+    }
+
+    pub fn is_jump(&self) -> bool {
+        self.lt || self.eq || self.gt
+    }
+}
+
+impl fmt::Display for Jump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match (self.lt, self.eq, self.gt) {
+            (false, false, false) => "",
+            (false, false, true) => "JGT",
+            (false, true, false) => "JEQ",
+            (false, true, true) => "JGE",
+            (true, false, false) => "JLT",
+            (true, false, true) => "JNE",
+            (true, true, false) => "JLE",
+            (true, true, true) => "JMP",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Literal(usize),
+    Variable(String),
+    Expr(Expr),
+}
+
+impl Value {
+    pub fn from_string(input: &str) -> Result<Self, AsmErrorKind> {
+        if input.is_empty() {
+            Err(AsmErrorKind::EmptyValue)
+        } else if let Some(n) = parse_number(input) {
+            Ok(Value::Literal(n?))
+        } else if is_symbol(input) {
+            Ok(Value::Variable(input.to_string()))
+        } else if input.contains(|c: char| "+-*/&|()".contains(c)) {
+            Ok(Value::Expr(Expr::from_string(input)?))
+        } else {
+            Err(AsmErrorKind::InvalidSymbol(input.to_string()))
+        }
+    }
+
+    // Only literals have an encoding, and only those that fit in 15 bits, since anything wider would set the
+    // high bit and turn into a C-instruction. Symbols must have been resolved first.
+    pub fn to_binary(&self) -> Result<String, AsmErrorKind> {
+        match self {
+            Value::Literal(l) if *l <= MAX_LITERAL => Ok(format!("{:016b}", l)),
+            Value::Literal(l) => Err(AsmErrorKind::ValueOutOfRange(l.to_string())),
+            Value::Variable(v) => Err(AsmErrorKind::UndefinedSymbol(v.clone())),
+            Value::Expr(e) => {
+                let symbol = e.symbols().first().map_or_else(|| e.to_string(), |s| s.to_string());
+                Err(AsmErrorKind::UndefinedSymbol(symbol))
+            },
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Literal(l) => write!(f, "{}", l),
+            Value::Variable(v) => write!(f, "{}", v),
+            Value::Expr(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+pub const MAX_LITERAL: usize = 0x7FFF;
+pub const ROM_SIZE: usize = MAX_LITERAL + 1;
+
+// Numbers are decimal, `0x` hex, `0b` binary or a printable ASCII character in single quotes, and must fit
+// in the 15 bits of an A-instruction. Returns None if the input isn't written as a number at all.
+pub(crate) fn parse_number(input: &str) -> Option<Result<usize, AsmErrorKind>> {
+    Some(parse_unsigned(input)?.and_then(|n| match usize::try_from(n) {
+        Ok(n) if n <= MAX_LITERAL => Ok(n),
+        _ => Err(AsmErrorKind::ValueOutOfRange(input.to_string())),
+    }))
+}
+
+// Any number in one of the forms parse_number accepts, without checking its range
+pub(crate) fn parse_unsigned(input: &str) -> Option<Result<u64, AsmErrorKind>> {
+    let invalid = || AsmErrorKind::InvalidLiteral(input.to_string());
+    let parsed = if let Some(hex) = input.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).map_err(|_| invalid())
+    } else if let Some(bin) = input.strip_prefix("0b") {
+        u64::from_str_radix(bin, 2).map_err(|_| invalid())
+    } else if input.starts_with('\'') {
+        let mut chars = input.chars();
+        match (chars.next(), chars.next(), chars.next(), chars.next()) {
+            (Some('\''), Some(c), Some('\''), None) if (' '..='~').contains(&c) => Ok(c as u64),
+            _ => Err(invalid()),
+        }
+    } else if !input.is_empty() && input.chars().all(|c| c.is_ascii_digit()) {
+        // Too many digits for a u64 is still just out of range
+        Ok(input.parse::<u64>().unwrap_or(u64::MAX))
+    } else {
+        return None;
+    };
+    Some(parsed)
+}
+
+// Loading a negative constant is a pseudo-instruction, since A-instructions only hold 15 bit values:
+//
+//     @-5    expands to    @5
+//                          D=-A
+//
+// which leaves -5 in D. Like any A-instruction it overwrites A, and it also overwrites D. Only a single
+// number or symbol can be negated, since `@-5+3` would otherwise load -8. Returns None if the input is not
+// a negative A-instruction.
+pub fn expand_negative(input: &str) -> Option<Result<Vec<Program>, AsmError>> {
+    let value = input.strip_prefix("@-")?;
+    let load = match Instr::from_string(&format!("@{}", value)) {
+        Ok(Instr::A(Value::Expr(_))) => {
+            Err(AsmError::new(AsmErrorKind::NegativeExpression(value.to_string()), 2, value))
+        },
+        load => load.map_err(|mut e| {
+            // Point past the '-' that was removed
+            if e.column > 0 {
+                e.column += 1;
+            }
+            e
+        }),
+    };
+    let negate = Instr::C(Dest { a: false, d: true, m: false }, Comp::Neg(Reg::A), Jump::new());
+    Some(load.map(|load| vec![Program::Instr(load), Program::Instr(negate)]))
+}
+
+// Symbols are any sequence of letters, digits, '_', '.', '$' and ':' that does not begin with a digit
+pub(crate) fn is_symbol(input: &str) -> bool {
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || "_.$:".contains(c);
+    match input.chars().next() {
+        Some(c) => !c.is_ascii_digit() && input.chars().all(valid_char),
+        None => false,
+    }
+}
+
+impl Program {
+    pub fn from_string(input : &str) -> Result<Self, AsmError> {
+        match input.chars().next() {
+            Some(c) => {
+                match c {
+                    '(' => {
+                        let label = input[1..].strip_suffix(')').ok_or_else(|| {
+                            AsmError::new(AsmErrorKind::UnterminatedLabel, 0, input)
+                        })?;
+                        if label.is_empty() {
+                            Err(AsmError::new(AsmErrorKind::EmptyLabel, 0, input))
+                        } else if !is_symbol(label) {
+                            Err(AsmError::new(AsmErrorKind::InvalidSymbol(label.to_string()), 1, label))
+                        } else {
+                            Ok(Program::Label(label.to_string()))
+                        }
+                    },
+                    _ => Ok(Program::Instr(Instr::from_string(input)?)),
+                }
+            },
+            None => Err(AsmError::new(AsmErrorKind::EmptyInstruction, 0, input)),
+        }
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Program::Label(label) => write!(f, "({})", label),
+            Program::Instr(instr) => write!(f, "{}", instr),
+            Program::Export(label) => write!(f, ".export {}", label),
+            Program::Import(label) => write!(f, ".import {}", label),
+            Program::Source(line) => write!(f, "{}", line),
+            Program::Data(name) => write!(f, ".data {}", name),
+            Program::Word(values) => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                write!(f, ".word {}", values.join(", "))
+            },
+        }
+    }
+}
+
+impl Instr {
+    pub fn from_string(input : &str) -> Result<Self, AsmError> {
+        match input.chars().next() {
+            Some(c) => {
+                match c {
+                    '@' => Value::from_string(&input[1..])
+                        .map(Instr::A)
+                        .map_err(|kind| match kind {
+                            AsmErrorKind::EmptyValue => AsmError::new(kind, 0, input),
+                            _ => AsmError::new(kind, 1, &input[1..]),
+                        }),
+                    _ => {
+                        // dest=comp;jump
+                        let mut comp = input;
+                        let mut comp_col = 0;
+                        let mut dest = Dest::new();
+                        let mut jump = Jump::new();
+                        if let Some((dest_str, rest))  = input.split_once('=') {
+                            let dest_str = dest_str.trim_end();
+                            dest = Dest::from_string(dest_str).map_err(|kind| AsmError::new(kind, 0, dest_str))?;
+                            comp = rest;
+                            comp_col = dest_str.len() + 1;
+                        }
+                        if let Some((front, jump_str))  = comp.split_once(';') {
+                            let jump_col = comp_col + front.len() + 1;
+                            let jump_str = jump_str.trim_start();
+                            let jump_col = jump_col + comp[front.len() + 1..].len() - jump_str.len();
+                            jump = Jump::from_string(jump_str)
+                                .map_err(|kind| AsmError::new(kind, jump_col, jump_str))?;
+                            comp = front;
+                        }
+                        let comp_col = comp_col + comp.len() - comp.trim_start().len();
+                        let comp = comp.trim();
+                        let comp = Comp::from_string(comp).map_err(|kind| AsmError::new(kind, comp_col, comp))?;
+                        Ok(Instr::C(dest, comp, jump))
+                    },
+                }
+            },
+            None => Err(AsmError::new(AsmErrorKind::EmptyInstruction, 0, input)),
+        }
+    }
+
+    pub fn to_binary(&self) -> Result<String, AsmErrorKind> {
+        match self {
+            Instr::A(val) => val.to_binary(),
+            Instr::C(dest, comp, jump) => {
+                // 1 1 1 a c1 c2 c3 c4 c5 c6 d1 d2 d3 j1 j2 j3
+                let bin = "111".to_string();
+                Ok(bin + &comp.to_binary() + &dest.to_binary() + &jump.to_binary())
+            },
+        }
+    }
+
+    pub fn to_word(&self) -> Result<u16, AsmErrorKind> {
+        let bin = self.to_binary()?;
+        Ok(u16::from_str_radix(&bin, 2).expect("instructions encode to 16 binary digits"))
+    }
+
+    // The inverse of to_word. Words with the high bit clear are A-instructions; anything else must be
+    // 111a cccc ccdd djjj with a computation from the comp table.
+    pub fn from_word(word: u16) -> Result<Self, AsmErrorKind> {
+        if word & 0x8000 == 0 {
+            return Ok(Instr::A(Value::Literal(word as usize)));
+        }
+        if word & 0xE000 != 0xE000 {
+            return Err(AsmErrorKind::ReservedBits(format!("{:03b}", word >> 13)));
+        }
+        let comp = Comp::from_bits((word >> 6) & 0x7F)?;
+        Ok(Instr::C(Dest::from_bits((word >> 3) & 0b111), comp, Jump::from_bits(word & 0b111)))
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instr::A(val) => write!(f, "@{}", val),
+            Instr::C(dest, comp, jump) => {
+                if dest != &Dest::new() {
+                    write!(f, "{}=", dest)?;
+                }
+                write!(f, "{}", comp)?;
+                if jump.is_jump() {
+                    write!(f, ";{}", jump)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand_negative, Instr, Value, Dest, Jump, Program};
+    use crate::comp::{Comp, Reg};
+    use crate::error::{AsmError, AsmErrorKind};
+
+    #[test]
+    fn parse_a_instr() {
+        let code = "@6";
+        assert_eq!(Instr::A(Value::Literal(6)), Instr::from_string(code).unwrap());
+        assert_eq!(Instr::A(Value::Literal(6)).to_binary().unwrap(),   "0000000000000110");
+        assert_eq!(Instr::A(Value::Literal(56)).to_binary().unwrap(),  "0000000000111000");
+        assert_eq!(Instr::A(Value::Literal(1001)).to_binary().unwrap(),"0000001111101001");
+    }
+
+     #[test]
+    fn parse_c_instr() {
+        let code = "D-1";
+        let instr = Instr::C(Dest::new(), Comp::Dec(Reg::D), Jump::new());
+        assert_eq!(instr, Instr::from_string(code).unwrap());
+        assert_eq!(instr.to_binary().unwrap(),                            "1110001110000000");
+        assert_eq!(Instr::from_string("D|M").unwrap().to_binary().unwrap(),        "1111010101000000");
+        assert_eq!(Instr::from_string("D|A").unwrap().to_binary().unwrap(),        "1110010101000000");
+        assert_eq!(Instr::from_string("MD=M+1").unwrap().to_binary().unwrap(),     "1111110111011000");
+        assert_eq!(Instr::from_string("MD=M+1;JGE").unwrap().to_binary().unwrap(), "1111110111011011");
+        assert_eq!(Instr::from_string("M=A").unwrap().to_binary().unwrap(), "1110110000001000");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Instr::from_string("D;JMPP"),
+            Err(AsmError::new(AsmErrorKind::InvalidJump("JMPP".to_string()), 2, "JMPP")));
+        assert_eq!(Instr::from_string("AM=D*M;JMP"),
+            Err(AsmError::new(AsmErrorKind::InvalidComp("D*M".to_string()), 3, "D*M")));
+        assert_eq!(Instr::from_string("@"), Err(AsmError::new(AsmErrorKind::EmptyValue, 0, "@")));
+        assert_eq!(Instr::from_string("@1x"),
+            Err(AsmError::new(AsmErrorKind::InvalidSymbol("1x".to_string()), 1, "1x")));
+        assert_eq!(Program::from_string(""), Err(AsmError::new(AsmErrorKind::EmptyInstruction, 0, "")));
+        assert_eq!(Program::from_string("(LOOP"), Err(AsmError::new(AsmErrorKind::UnterminatedLabel, 0, "(LOOP")));
+        assert_eq!(Program::from_string("()"), Err(AsmError::new(AsmErrorKind::EmptyLabel, 0, "()")));
+    }
+
+    #[test]
+    fn dest_orderings() {
+        for (input, canonical) in [("MD=1", "MD=1"), ("DM=1", "MD=1"), ("AMD=1", "AMD=1"), ("ADM=1", "AMD=1"), ("AD=1", "AD=1")] {
+            assert_eq!(Instr::from_string(input).unwrap().to_string(), canonical);
+        }
+        for dest in ["X", "AAA", "MQ", "DA", "MA", "DMA", "", "AMDM"] {
+            let input = format!("{}=D", dest);
+            let expected = AsmError::new(AsmErrorKind::InvalidDest(dest.to_string()), 0, dest);
+            assert_eq!(Instr::from_string(&input), Err(expected));
+        }
+        let errors = crate::assemble("@1\nD=A\nMQ=0").unwrap_err();
+        assert_eq!((errors[0].line, errors[0].column), (3, 1));
+    }
+
+    #[test]
+    fn comp_spellings() {
+        for (input, canonical) in [
+            ("D=A+D", "D=D+A"), ("M=M+D", "M=D+M"), ("D=A&D", "D=D&A"), ("D=M|D", "D=D|M"),
+            ("D=D + M", "D=D+M"), ("D=D +1", "D=D+1"), ("D=1+D", "D=D+1"), ("A = M-D ; JGT", "A=M-D;JGT"),
+        ] {
+            let instr = Instr::from_string(input).unwrap();
+            assert_eq!(instr.to_string(), canonical);
+            assert_eq!(instr.to_binary().unwrap(), Instr::from_string(canonical).unwrap().to_binary().unwrap());
+        }
+        for input in ["D=A+M", "D=1-D", "D=D+D", "D=!1", "D=M*D", "D=-0"] {
+            assert!(matches!(Instr::from_string(input).unwrap_err().kind, AsmErrorKind::InvalidComp(_)), "{}", input);
+        }
+        assert_eq!(Instr::from_string("D= D+X").unwrap_err().column, 3);
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(Instr::from_string("@0x4000").unwrap(), Instr::A(Value::Literal(16384)));
+        assert_eq!(Instr::from_string("@0b101").unwrap(), Instr::A(Value::Literal(5)));
+        assert_eq!(Instr::from_string("@'A'").unwrap(), Instr::A(Value::Literal(65)));
+        assert_eq!(Instr::from_string("@32767").unwrap().to_binary().unwrap(), "0111111111111111");
+        assert_eq!(Value::Literal(32768).to_binary(), Err(AsmErrorKind::ValueOutOfRange("32768".to_string())));
+        assert_eq!(Value::Variable("i".to_string()).to_binary(), Err(AsmErrorKind::UndefinedSymbol("i".to_string())));
+        assert_eq!(Instr::from_string("@32768"),
+            Err(AsmError::new(AsmErrorKind::ValueOutOfRange("32768".to_string()), 1, "32768")));
+        assert_eq!(Instr::from_string("@99999999999999999999999").unwrap_err().kind,
+            AsmErrorKind::ValueOutOfRange("99999999999999999999999".to_string()));
+        assert_eq!(Instr::from_string("@0x8000").unwrap_err().kind, AsmErrorKind::ValueOutOfRange("0x8000".to_string()));
+        assert_eq!(Instr::from_string("@0xZZ").unwrap_err().kind, AsmErrorKind::InvalidLiteral("0xZZ".to_string()));
+        assert_eq!(Instr::from_string("@'ab'").unwrap_err().kind, AsmErrorKind::InvalidLiteral("'ab'".to_string()));
+    }
+
+    #[test]
+    fn negative_literals() {
+        let expanded = expand_negative("@-5").unwrap().unwrap();
+        let text: Vec<String> = expanded.iter().map(|p| p.to_string()).collect();
+        assert_eq!(text, vec!["@5", "D=-A"]);
+        assert_eq!(expand_negative("@-40000").unwrap().unwrap_err().column, 2);
+        assert_eq!(expand_negative("@5"), None);
+        assert_eq!(expand_negative("@-5+3").unwrap(),
+            Err(AsmError::new(AsmErrorKind::NegativeExpression("5+3".to_string()), 2, "5+3")));
+        assert!(expand_negative("@-SIZE").unwrap().is_ok());
+    }
+
+    #[test]
+    fn error_display() {
+        let err = Instr::from_string("D;JMPP").unwrap_err().at("Max.asm", 12, 5, "    D;JMPP // loop");
+        assert_eq!(err.to_string(), "Max.asm:12:7: error: invalid jump \"JMPP\"\n   |\n12 |     D;JMPP // loop\n   |       ^^^^");
+    }
+}
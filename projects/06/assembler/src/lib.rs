@@ -0,0 +1,184 @@
+mod comp;
+mod data;
+mod directive;
+mod disasm;
+mod error;
+mod expr;
+mod format;
+mod instr;
+mod lint;
+mod listing;
+mod local;
+mod object;
+mod optimize;
+mod parse;
+mod reachable;
+mod symbols;
+
+pub use comp::{Comp, Reg};
+pub use data::{layout_data, DataBlock, Prologue, DATA_END, DATA_START};
+pub use disasm::{disassemble, parse_hack, parse_hack_lines};
+pub use error::{AsmError, AsmErrorKind};
+pub use expr::{Expr, Op};
+pub use format::format;
+pub use directive::Directive;
+pub use instr::{expand_negative, Dest, Instr, Jump, Program, Statement, Value, ROM_SIZE};
+pub use lint::{lint, Warning, WarningKind};
+pub use listing::listing;
+pub use local::scope_local_labels;
+pub use object::{assemble_object, link, LinkError, Object, ObjectWord};
+pub use optimize::{optimize, Savings};
+pub use parse::parse;
+pub use symbols::{SymbolKind, SymbolTable};
+
+// The result of assembling a program: one 16 bit word per ROM address, and the symbol table with every
+// label and variable resolved
+#[derive(Debug, Clone)]
+pub struct Assembled {
+    pub words: Vec<u16>,
+    pub symbols: SymbolTable,
+}
+
+impl Assembled {
+    // The text `.hack` format: one word per line as 16 binary digits
+    pub fn to_hack(&self) -> String {
+        self.words.iter().map(|w| format!("{:016b}\n", w)).collect()
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Assembled, Vec<AsmError>> {
+    parse("<input>", source).and_then(|statements| resolve(&statements))
+}
+
+pub fn resolve(program: &[Statement]) -> Result<Assembled, Vec<AsmError>> {
+    let program = scope_local_labels(program)?;
+    // Data blocks take the start of RAM, and the code that initializes them the start of ROM
+    let (blocks, prologue) = layout_data(&program)?;
+    let mut errors = Vec::new();
+
+    // First pass: Go through commands, seperate labels and instructions, building symbol table
+    let mut symbols = SymbolTable::new();
+
+    let mut instructions = prologue;
+    for statement in &program {
+        match &statement.program {
+            Program::Label(label) => {
+                symbols.insert(label.clone(), instructions.len(), SymbolKind::Label);
+            },
+            Program::Instr(instr) => {
+                instructions.push((instr.clone(), statement));
+            },
+            // Everything is visible when assembling a whole program at once
+            Program::Export(_) | Program::Import(_) | Program::Source(_) => {},
+            Program::Data(_) | Program::Word(_) => {},
+        };
+    }
+    if instructions.len() > ROM_SIZE {
+        let (_, statement) = instructions[ROM_SIZE];
+        let kind = AsmErrorKind::ProgramTooLarge(instructions.len());
+        return Err(vec![statement.error(kind, statement.code())]);
+    }
+    for block in &blocks {
+        if symbols.get(&block.name).is_some() {
+            let statement = program.iter().find(|s| s.program == Program::Data(block.name.clone())).unwrap();
+            errors.push(statement.error(AsmErrorKind::DuplicateSymbol(block.name.clone()), &block.name));
+        }
+        symbols.insert(block.name.clone(), block.address, SymbolKind::Data);
+    }
+
+    // Second pass: go through instructions and replace variables with value of label. Variables that don't
+    // have labels are allocated in registers after the data blocks, and replaced with their value.
+    // Expressions can use any label, but only the variables that have been allocated by the time they are
+    // reached.
+    let mut next_var = DATA_START + blocks.iter().map(|b| b.len).sum::<usize>();
+    let mut words = Vec::new();
+    for (instr, statement) in &instructions {
+        let literal = match instr {
+            Instr::A(Value::Literal(l)) => *l,
+            Instr::A(Value::Variable(v)) => {
+                match symbols.get(v.as_str()) {
+                    Some(idx) => *idx,
+                    None if next_var == DATA_END => {
+                        errors.push(statement.error(AsmErrorKind::OutOfRam(v.clone()), v));
+                        continue;
+                    },
+                    None => {
+                        symbols.insert(v.clone(), next_var, SymbolKind::Variable);
+                        next_var+=1;
+                        next_var - 1
+                    },
+                }
+            },
+            Instr::A(Value::Expr(e)) => match e.eval(&|s| symbols.get(s).copied()) {
+                Ok(l) => l,
+                Err(kind) => {
+                    errors.push(statement.error(kind, &e.to_string()));
+                    continue;
+                },
+            },
+            Instr::C(_, _, _) => {
+                words.push(instr.to_word().expect("C-instructions always have an encoding"));
+                continue;
+            },
+        };
+        match Instr::A(Value::Literal(literal)).to_word() {
+            Ok(word) => words.push(word),
+            Err(kind) => errors.push(statement.error(kind, statement.code())),
+        }
+    }
+    if errors.is_empty() {
+        Ok(Assembled { words, symbols })
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, AsmErrorKind};
+
+    #[test]
+    fn assemble_max() {
+        let assembled = assemble(include_str!("../../max/Max.asm")).unwrap();
+        assert_eq!(assembled.to_hack(), include_str!("../max.hack"));
+        assert_eq!(assembled.symbols.get("OUTPUT_FIRST"), Some(&10));
+        assert_eq!(assembled.symbols.get("INFINITE_LOOP"), Some(&14));
+    }
+
+    #[test]
+    fn variables_allocated_from_16() {
+        let assembled = assemble("@i\nM=1\n@sum\nM=0\n@i\nD=M").unwrap();
+        assert_eq!(assembled.words, vec![16, 0xEFC8, 17, 0xEA88, 16, 0xFC10]);
+    }
+
+    #[test]
+    fn expressions() {
+        let assembled = assemble("@i\nM=0\n@SCREEN+32*5\n@END+1\n@i+1\n(END)").unwrap();
+        assert_eq!(assembled.words, vec![16, 0xEA88, 16544, 6, 17]);
+        let errors = assemble("@i+1\n@i\n@KBD*2").unwrap_err();
+        assert_eq!(errors.iter().map(|e| (e.line, e.column)).collect::<Vec<_>>(), vec![(1, 2), (3, 2)]);
+        assert_eq!(errors[0].kind, AsmErrorKind::UndefinedSymbol("i".to_string()));
+    }
+
+    #[test]
+    fn negative_constants() {
+        let assembled = assemble("@-5\nM=D\n@-0x10").unwrap();
+        assert_eq!(assembled.words, vec![5, 0xECD0, 0xE308, 16, 0xECD0]);
+    }
+
+    #[test]
+    fn rom_size() {
+        let full = "D=0\n".repeat(32767) + "(END)\n@END\n";
+        assert_eq!(assemble(&full).unwrap().words.len(), 32768);
+        let source = "D=0\n".repeat(32768) + "(END)\n@END\n";
+        let errors = assemble(&source).unwrap_err();
+        assert_eq!(errors.iter().map(|e| (e.line, e.column)).collect::<Vec<_>>(), vec![(32770, 1)]);
+        assert_eq!(errors[0].kind, AsmErrorKind::ProgramTooLarge(32769));
+    }
+
+    #[test]
+    fn collects_all_errors() {
+        let errors = assemble("@1x\nD=M\nD;JXX").unwrap_err();
+        assert_eq!(errors.iter().map(|e| e.line).collect::<Vec<_>>(), vec![1, 3]);
+    }
+}
@@ -0,0 +1,202 @@
+use std::{collections::HashMap, fmt};
+
+use crate::comp::{Comp, Reg};
+use crate::instr::{Instr, Program, Statement, Value};
+use crate::local::scope_local_labels;
+use crate::symbols::SymbolTable;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum WarningKind {
+    DuplicateLabel(String, usize),
+    UnusedLabel(String),
+    SingleUseVariable(String),
+    JumpWithoutTarget,
+    WritesAAndM,
+    Unreachable,
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WarningKind::DuplicateLabel(l, first) => {
+                write!(f, "label {:?} is already defined on line {}, this definition replaces it", l, first)
+            },
+            WarningKind::UnusedLabel(l) => write!(f, "label {:?} is never referenced", l),
+            WarningKind::SingleUseVariable(v) => {
+                write!(f, "variable {:?} is only referenced once, is it misspelled?", v)
+            },
+            WarningKind::JumpWithoutTarget => {
+                write!(f, "jump is not preceded by an instruction that sets A to its target")
+            },
+            WarningKind::WritesAAndM => {
+                write!(f, "A is overwritten with a value read from M, and M is written at the old address in A")
+            },
+            WarningKind::Unreachable => write!(f, "instruction is unreachable after an unconditional jump"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub file: String,
+    pub line: usize,
+}
+
+impl Warning {
+    fn new(kind: WarningKind, statement: &Statement) -> Self {
+        Warning { kind, file: statement.file.clone(), line: statement.line }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: warning: {}", self.file, self.line, self.kind)
+    }
+}
+
+// Look for code that assembles but probably doesn't do what was meant. Warnings are ordered by line, then
+// by kind and symbol, so the output is the same every run.
+pub fn lint(program: &[Statement]) -> Vec<Warning> {
+    // Local labels in different scopes are different labels. Scoping errors are reported when assembling.
+    let scoped = scope_local_labels(program);
+    let program = scoped.as_deref().unwrap_or(program);
+    let predefined = SymbolTable::new();
+    let mut warnings = Vec::new();
+
+    // Where each label is defined, and the statements referring to each symbol
+    let mut labels: HashMap<&str, &Statement> = HashMap::new();
+    let mut references: HashMap<&str, Vec<&Statement>> = HashMap::new();
+    for statement in program {
+        match &statement.program {
+            Program::Label(label) => {
+                if let Some(first) = labels.insert(label, statement) {
+                    warnings.push(Warning::new(WarningKind::DuplicateLabel(label.clone(), first.line), statement));
+                }
+            },
+            // Data blocks are defined like labels, but are as much in use as the words they hold
+            Program::Data(name) => {
+                labels.insert(name, statement);
+                references.entry(name).or_default().push(statement);
+            },
+            Program::Instr(Instr::A(Value::Variable(v))) | Program::Export(v) | Program::Import(v) => {
+                references.entry(v).or_default().push(statement)
+            },
+            Program::Instr(Instr::A(Value::Expr(e))) => {
+                for symbol in e.symbols() {
+                    references.entry(symbol).or_default().push(statement);
+                }
+            },
+            Program::Instr(_) | Program::Word(_) | Program::Source(_) => {},
+        }
+    }
+    for (label, statement) in &labels {
+        if !references.contains_key(label) {
+            warnings.push(Warning::new(WarningKind::UnusedLabel(label.to_string()), statement));
+        }
+    }
+    for (symbol, statements) in &references {
+        if statements.len() == 1 && !labels.contains_key(symbol) && predefined.get(symbol).is_none() {
+            warnings.push(Warning::new(WarningKind::SingleUseVariable(symbol.to_string()), statements[0]));
+        }
+    }
+
+    // Walk the instructions in order, tracking whether A was just set and whether control can fall through
+    let mut sets_a = false;
+    let mut reachable = true;
+    for statement in program {
+        match &statement.program {
+            Program::Label(_) => {
+                // A label can be jumped to from anywhere, so A is unknown and the code is reachable again
+                sets_a = false;
+                reachable = true;
+            },
+            Program::Export(_) | Program::Import(_) | Program::Data(_) | Program::Word(_) | Program::Source(_) => {},
+            Program::Instr(instr) => {
+                if !reachable {
+                    warnings.push(Warning::new(WarningKind::Unreachable, statement));
+                    // One warning per run of unreachable code
+                    reachable = true;
+                }
+                match instr {
+                    Instr::A(_) => sets_a = true,
+                    Instr::C(dest, comp, jump) => {
+                        if jump.is_jump() && !sets_a {
+                            warnings.push(Warning::new(WarningKind::JumpWithoutTarget, statement));
+                        }
+                        // `AM=M-1` and `AM=M+1` move a pointer like SP and leave A at its new value, which is
+                        // how VM code pops and pushes
+                        let moves_pointer = matches!(comp, Comp::Inc(Reg::M) | Comp::Dec(Reg::M));
+                        if dest.a && dest.m && comp.reads_m() && !moves_pointer {
+                            warnings.push(Warning::new(WarningKind::WritesAAndM, statement));
+                        }
+                        if jump.lt && jump.eq && jump.gt {
+                            reachable = false;
+                        }
+                        sets_a = dest.a;
+                    },
+                }
+            },
+        }
+    }
+
+    warnings.sort_by(|a, b| (&a.file, a.line, &a.kind).cmp(&(&b.file, b.line, &b.kind)));
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lint, WarningKind};
+    use crate::parse;
+
+    fn lint_source(source: &str) -> Vec<(usize, WarningKind)> {
+        lint(&parse("<input>", source).unwrap()).into_iter().map(|w| (w.line, w.kind)).collect()
+    }
+
+    #[test]
+    fn clean_program() {
+        assert_eq!(lint_source(include_str!("../../max/Max.asm")), vec![]);
+    }
+
+    #[test]
+    fn labels_and_variables() {
+        let source = "(LOOP)\n@count\nM=M+1\n@LOOP\n0;JMP\n(LOOP)\n(END)\n@cuont\nM=0\n@count\nD=M";
+        assert_eq!(lint_source(source), vec![
+            (6, WarningKind::DuplicateLabel("LOOP".to_string(), 1)),
+            (7, WarningKind::UnusedLabel("END".to_string())),
+            (8, WarningKind::SingleUseVariable("cuont".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn control_flow() {
+        let source = "@R0\nAM=D+M\nAM=D;JGT\n@R1\nA=M\n0;JMP\nD=0\nM=D\n(END)\nD;JEQ";
+        assert_eq!(lint_source(source), vec![
+            (2, WarningKind::WritesAAndM),
+            (7, WarningKind::Unreachable),
+            (9, WarningKind::UnusedLabel("END".to_string())),
+            (10, WarningKind::JumpWithoutTarget),
+        ]);
+    }
+
+    #[test]
+    fn vm_stack() {
+        let source = "@SP\nAM=M-1\nD=M\nA=A-1\nM=D+M\n@SP\nAM=M+1\nA=A-1\nM=-1";
+        assert_eq!(lint_source(source), vec![]);
+    }
+
+    #[test]
+    fn warnings_on_one_line() {
+        let source = "(L)\n(L)\n@zz+yy+xx\nD=A";
+        let expected = vec![
+            (2, WarningKind::DuplicateLabel("L".to_string(), 1)),
+            (2, WarningKind::UnusedLabel("L".to_string())),
+            (3, WarningKind::SingleUseVariable("xx".to_string())),
+            (3, WarningKind::SingleUseVariable("yy".to_string())),
+            (3, WarningKind::SingleUseVariable("zz".to_string())),
+        ];
+        for _ in 0..10 {
+            assert_eq!(lint_source(source), expected);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::data::layout_data;
+use crate::instr::{Instr, Program, Statement, Value};
+use crate::Assembled;
+
+// A listing puts every ROM word next to the source that produced it:
+//
+//   ROM   HEX   BINARY            SYMBOL                   LINE  SOURCE
+//   00010                         OUTPUT_FIRST = 10          18  (OUTPUT_FIRST)
+//   00010 0000  0000000000000000  R0 = 0                     19     @R0
+//
+// Label definitions are shown at the address they resolve to, with no encoding of their own. The code
+// initializing data blocks comes first, listed as the instructions generated for each `.word` line.
+pub fn listing(program: &[Statement], assembled: &Assembled) -> String {
+    let mut out = format!("{:<5} {:<5} {:<17} {:<23} {:>5}  SOURCE\n", "ROM", "HEX", "BINARY", "SYMBOL", "LINE");
+    let mut addr = 0;
+    let (blocks, prologue) = layout_data(program).unwrap_or_default();
+    for (instr, statement) in prologue {
+        let word = assembled.words[addr];
+        out += &format!("{:05} {:04X}  {:016b}  {:<23} {:>5}     {}\n", addr, word, word, "", statement.line, instr);
+        addr += 1;
+    }
+    for statement in program {
+        let text = statement.text.trim_end();
+        match &statement.program {
+            Program::Label(label) => {
+                let symbol = format!("{} = {}", label, addr);
+                out += &format!("{:05} {:<5} {:<17} {:<23} {:>5}  {}\n", addr, "", "", symbol, statement.line, text);
+            },
+            Program::Data(name) => {
+                let address = blocks.iter().find(|b| &b.name == name).map_or(0, |b| b.address);
+                let symbol = format!("{} = {}", name, address);
+                out += &format!("{:<5} {:<5} {:<17} {:<23} {:>5}  {}\n", "", "", "", symbol, statement.line, text);
+            },
+            Program::Export(_) | Program::Import(_) | Program::Word(_) | Program::Source(_) => {
+                out += &format!("{:<5} {:<5} {:<17} {:<23} {:>5}  {}\n", "", "", "", "", statement.line, text);
+            },
+            Program::Instr(instr) => {
+                let word = assembled.words[addr];
+                let symbol = match instr {
+                    Instr::A(Value::Variable(v)) => format!("{} = {}", v, word),
+                    Instr::A(Value::Expr(e)) => format!("{} = {}", e, word),
+                    _ => String::new(),
+                };
+                out += &format!("{:05} {:04X}  {:016b}  {:<23} {:>5}  {}\n", addr, word, word, symbol, statement.line, text);
+                addr += 1;
+            },
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::listing;
+    use crate::{parse, resolve};
+
+    #[test]
+    fn list_labels_and_symbols() {
+        let program = parse("<input>", "@i // counter\nM=1\n(LOOP)\n  @LOOP\n  0;JMP").unwrap();
+        let lines: Vec<String> = listing(&program, &resolve(&program).unwrap()).lines().map(|l| l.to_string()).collect();
+        assert_eq!(lines[1], "00000 0010  0000000000010000  i = 16                      1  @i // counter");
+        assert_eq!(lines[2], "00001 EFC8  1110111111001000                              2  M=1");
+        assert_eq!(lines[3], "00002                         LOOP = 2                    3  (LOOP)");
+        assert_eq!(lines[4], "00002 0002  0000000000000010  LOOP = 2                    4    @LOOP");
+    }
+
+    #[test]
+    fn list_data_prologue() {
+        let program = parse("<input>", ".data T\n.word 0\n@T").unwrap();
+        let lines: Vec<String> = listing(&program, &resolve(&program).unwrap()).lines().map(|l| l.to_string()).collect();
+        assert_eq!(lines[1], "00000 0010  0000000000010000                              2     @16");
+        assert_eq!(lines[2], "00001 EA88  1110101010001000                              2     M=0");
+        assert_eq!(lines[3], "                              T = 16                      1  .data T");
+        assert_eq!(lines[4], "                                                          2  .word 0");
+        assert_eq!(lines[5], "00002 0010  0000000000010000  T = 16                      3  @T");
+    }
+}
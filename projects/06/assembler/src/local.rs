@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use crate::error::{AsmError, AsmErrorKind};
+use crate::expr::Expr;
+use crate::instr::{Instr, Program, Statement, Value};
+
+pub fn is_local(symbol: &str) -> bool {
+    symbol.starts_with('.')
+}
+
+// Local labels start with '.' and belong to the nearest global label before them, so `(.loop)` after
+// `(MULT)` is really `MULT.loop`. This qualifies every local label and every reference to one, so the
+// symbol table only ever sees global names. Referring to a local label from another scope is an error.
+pub fn scope_local_labels(program: &[Statement]) -> Result<Vec<Statement>, Vec<AsmError>> {
+    // The scopes each local label is defined in
+    let mut defined: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut scope = "";
+    for statement in program {
+        if let Program::Label(label) = &statement.program {
+            if is_local(label) {
+                defined.entry(label).or_default().push(scope);
+            } else {
+                scope = label;
+            }
+        }
+    }
+
+    let mut errors = Vec::new();
+    let mut scope = "";
+    let mut scoped = Vec::new();
+    for statement in program {
+        let mut qualify = |symbol: &str| -> String {
+            if !is_local(symbol) {
+                return symbol.to_string();
+            }
+            match defined.get(symbol) {
+                Some(scopes) if !scopes.contains(&scope) => {
+                    let kind = AsmErrorKind::LocalLabelOutOfScope(scopes[0].to_string());
+                    errors.push(statement.error(kind, symbol));
+                },
+                None => errors.push(statement.error(AsmErrorKind::UndefinedSymbol(symbol.to_string()), symbol)),
+                _ => {},
+            }
+            format!("{}{}", scope, symbol)
+        };
+        let program = match &statement.program {
+            Program::Label(label) if is_local(label) => Program::Label(format!("{}{}", scope, label)),
+            Program::Label(label) => {
+                scope = label;
+                Program::Label(label.clone())
+            },
+            Program::Instr(Instr::A(Value::Variable(v))) => Program::Instr(Instr::A(Value::Variable(qualify(v)))),
+            Program::Instr(Instr::A(Value::Expr(e))) => Program::Instr(Instr::A(Value::Expr(qualify_expr(e, &mut qualify)))),
+            p => p.clone(),
+        };
+        scoped.push(Statement { program, ..statement.clone() });
+    }
+    if errors.is_empty() {
+        Ok(scoped)
+    } else {
+        Err(errors)
+    }
+}
+
+fn qualify_expr(e: &Expr, qualify: &mut dyn FnMut(&str) -> String) -> Expr {
+    match e {
+        Expr::Symbol(s) => Expr::Symbol(qualify(s)),
+        Expr::Binary(l, op, r) => Expr::Binary(Box::new(qualify_expr(l, qualify)), *op, Box::new(qualify_expr(r, qualify))),
+        Expr::Literal(_) => e.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assemble, AsmErrorKind};
+
+    #[test]
+    fn local_labels() {
+        let source = "(MULT)\n(.loop)\n@.loop\n0;JMP\n(DIV)\n(.loop)\n@.loop+1\n0;JMP\n@.end\n(.end)";
+        let assembled = assemble(source).unwrap();
+        assert_eq!(assembled.words, vec![0, 0xEA87, 3, 0xEA87, 5]);
+        assert_eq!(assembled.symbols.get("MULT.loop"), Some(&0));
+        assert_eq!(assembled.symbols.get("DIV.loop"), Some(&2));
+        assert_eq!(assembled.symbols.get("DIV.end"), Some(&5));
+    }
+
+    #[test]
+    fn out_of_scope() {
+        let errors = assemble("(MULT)\n(.done)\n(DIV)\n@.done\n@.nowhere").unwrap_err();
+        assert_eq!((errors[0].line, errors[0].column), (4, 2));
+        assert_eq!(errors[0].kind, AsmErrorKind::LocalLabelOutOfScope("MULT".to_string()));
+        assert_eq!(errors[1].kind, AsmErrorKind::UndefinedSymbol(".nowhere".to_string()));
+    }
+}
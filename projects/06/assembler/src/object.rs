@@ -0,0 +1,343 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use crate::error::{AsmError, AsmErrorKind};
+use crate::instr::{Instr, Program, Statement, Value, MAX_LITERAL};
+use crate::local::scope_local_labels;
+use crate::symbols::{SymbolKind, SymbolTable};
+use crate::Assembled;
+
+const HEADER: &str = "HACKOBJ 1";
+
+// One ROM word of an object file, as it will be fixed up by the linker
+#[derive(Debug, PartialEq, Clone)]
+pub enum ObjectWord {
+    // Needs no fixing up: a C-instruction, a literal or a predefined symbol
+    Absolute(u16),
+    // An address within this module, which moves by wherever the module ends up in ROM
+    Relocatable(usize),
+    // A label named by `.import`, which another module must export
+    External(String),
+    // A symbol that is neither a label nor imported, shared with the other modules that use it
+    Variable(String),
+}
+
+// A separately assembled module. Labels are private to the module unless named by `.export`, and labels
+// of other modules are only visible once named by `.import`.
+//
+// The text format has a header line, then `export <label> <offset>` lines, then one line per ROM word
+// of `word <16 binary digits>`, `rel <offset>`, `ext <symbol>` or `var <symbol>`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Object {
+    pub exports: Vec<(String, usize)>,
+    pub code: Vec<ObjectWord>,
+}
+
+impl Object {
+    pub fn to_text(&self) -> String {
+        let mut out = format!("{}\n", HEADER);
+        for (label, offset) in &self.exports {
+            out += &format!("export {} {}\n", label, offset);
+        }
+        for word in &self.code {
+            out += &match word {
+                ObjectWord::Absolute(w) => format!("word {:016b}\n", w),
+                ObjectWord::Relocatable(offset) => format!("rel {}\n", offset),
+                ObjectWord::External(symbol) => format!("ext {}\n", symbol),
+                ObjectWord::Variable(symbol) => format!("var {}\n", symbol),
+            };
+        }
+        out
+    }
+
+    pub fn from_text(file: &str, text: &str) -> Result<Self, Vec<AsmError>> {
+        let mut object = Object { exports: Vec::new(), code: Vec::new() };
+        let mut errors = Vec::new();
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, l)| l.trim()) != Some(HEADER) {
+            let first = text.lines().next().unwrap_or("");
+            return Err(vec![AsmError::new(AsmErrorKind::InvalidObject(first.to_string()), 0, first).at(file, 1, 1, first)]);
+        }
+        for (line_idx, line) in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [] => {},
+                ["export", label, offset] if offset.parse::<usize>().is_ok() => {
+                    object.exports.push((label.to_string(), offset.parse().unwrap()))
+                },
+                ["word", w] if w.len() == 16 && u16::from_str_radix(w, 2).is_ok() => {
+                    object.code.push(ObjectWord::Absolute(u16::from_str_radix(w, 2).unwrap()))
+                },
+                ["rel", offset] if offset.parse::<usize>().is_ok() => {
+                    object.code.push(ObjectWord::Relocatable(offset.parse().unwrap()))
+                },
+                ["ext", symbol] => object.code.push(ObjectWord::External(symbol.to_string())),
+                ["var", symbol] => object.code.push(ObjectWord::Variable(symbol.to_string())),
+                _ => errors.push(
+                    AsmError::new(AsmErrorKind::InvalidObject(line.to_string()), 0, line.trim())
+                        .at(file, line_idx + 1, line.len() - line.trim_start().len() + 1, line),
+                ),
+            }
+        }
+        if errors.is_empty() {
+            Ok(object)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+// Assemble one module without resolving anything that depends on where it will be placed. Predefined
+// symbols are resolved now; labels become relocatable and imports and variables are left to the linker.
+pub fn assemble_object(program: &[Statement]) -> Result<Object, Vec<AsmError>> {
+    let program = &scope_local_labels(program)?;
+    let predefined = SymbolTable::new();
+    let mut labels = HashMap::new();
+    let mut imports = HashSet::new();
+    let mut idx = 0;
+    for statement in program {
+        match &statement.program {
+            Program::Label(label) => {
+                labels.insert(label.as_str(), idx);
+            },
+            Program::Import(label) => {
+                imports.insert(label.as_str());
+            },
+            Program::Instr(_) => idx += 1,
+            Program::Export(_) | Program::Data(_) | Program::Word(_) | Program::Source(_) => {},
+        }
+    }
+
+    let mut object = Object { exports: Vec::new(), code: Vec::new() };
+    let mut errors = Vec::new();
+    for statement in program {
+        let word = match &statement.program {
+            Program::Label(_) | Program::Source(_) => continue,
+            Program::Import(label) => {
+                if labels.contains_key(label.as_str()) || predefined.get(label).is_some() {
+                    errors.push(statement.error(AsmErrorKind::DuplicateSymbol(label.clone()), label));
+                }
+                continue;
+            },
+            Program::Data(_) | Program::Word(_) => {
+                errors.push(statement.error(AsmErrorKind::DataInObject, statement.text.trim()));
+                continue;
+            },
+            Program::Export(label) => {
+                match labels.get(label.as_str()) {
+                    Some(offset) => object.exports.push((label.clone(), *offset)),
+                    None => errors.push(statement.error(AsmErrorKind::UndefinedSymbol(label.clone()), label)),
+                }
+                continue;
+            },
+            Program::Instr(Instr::A(Value::Variable(v))) => match (labels.get(v.as_str()), predefined.get(v)) {
+                (Some(offset), _) => ObjectWord::Relocatable(*offset),
+                (None, Some(val)) => ObjectWord::Absolute(*val as u16),
+                (None, None) if imports.contains(v.as_str()) => ObjectWord::External(v.clone()),
+                (None, None) => ObjectWord::Variable(v.clone()),
+            },
+            Program::Instr(Instr::A(Value::Expr(e))) => {
+                // Evaluating with the module at two different bases shows whether the expression moves
+                // with the module (like `LOOP+2`), stays put (like `SCREEN+32`), or neither (like `LOOP*2`)
+                let at_base = |base: usize| {
+                    e.eval(&|s| labels.get(s).map(|offset| offset + base).or_else(|| predefined.get(s).copied()))
+                };
+                match (at_base(0), at_base(1)) {
+                    (Ok(v0), Ok(v1)) if v0 == v1 => ObjectWord::Absolute(v0 as u16),
+                    (Ok(v0), Ok(v1)) if v1 == v0 + 1 => ObjectWord::Relocatable(v0),
+                    (Err(kind), _) => {
+                        errors.push(statement.error(kind, &e.to_string()));
+                        continue;
+                    },
+                    _ => {
+                        errors.push(statement.error(AsmErrorKind::NotRelocatable(e.to_string()), &e.to_string()));
+                        continue;
+                    },
+                }
+            },
+            Program::Instr(instr) => match instr.to_word() {
+                Ok(word) => ObjectWord::Absolute(word),
+                Err(kind) => {
+                    errors.push(statement.error(kind, statement.code()));
+                    continue;
+                },
+            },
+        };
+        object.code.push(word);
+    }
+    if errors.is_empty() {
+        Ok(object)
+    } else {
+        Err(errors)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum LinkError {
+    // The label, the module exporting it first, and the module exporting it again
+    DuplicateExport(String, String, String),
+    // The label and the module exporting it
+    ExportOutOfRange(String, String),
+    AddressOutOfRange(String, usize),
+    RomOverflow(usize),
+    // The label and the module importing it
+    UndefinedImport(String, String),
+    // The label, the module exporting it, and the module using it without importing it
+    NotImported(String, String, String),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::DuplicateExport(label, first, second) => {
+                write!(f, "error: {:?} is exported by both {} and {}", label, first, second)
+            },
+            LinkError::ExportOutOfRange(label, module) => {
+                write!(f, "error: {}: export {:?} is outside the module", module, label)
+            },
+            LinkError::AddressOutOfRange(module, addr) => {
+                write!(f, "error: {}: relocated address {} does not fit in 15 bits", module, addr)
+            },
+            LinkError::RomOverflow(len) => write!(f, "error: program is {} words long but ROM holds 32768", len),
+            LinkError::UndefinedImport(label, module) => {
+                write!(f, "error: {}: imported label {:?} is not exported by any module", module, label)
+            },
+            LinkError::NotImported(label, exporter, module) => {
+                write!(f, "error: {}: {:?} is exported by {} but used as a variable, is it missing an .import?",
+                    module, label, exporter)
+            },
+        }
+    }
+}
+
+// Place each module in ROM after the one before it, then fix up every word. Every import must be
+// exported by some module. Variables are allocated from 16 in the order they are first used across all
+// modules, just as when assembling a single file, and may not share a name with an exported label.
+pub fn link(objects: &[(String, Object)]) -> Result<Assembled, Vec<LinkError>> {
+    let mut symbols = SymbolTable::new();
+    let mut exporters: HashMap<&str, &str> = HashMap::new();
+    let mut errors = Vec::new();
+    let mut bases = Vec::new();
+    let mut base = 0;
+    for (name, object) in objects {
+        bases.push(base);
+        for (label, offset) in &object.exports {
+            if *offset > object.code.len() {
+                errors.push(LinkError::ExportOutOfRange(label.clone(), name.clone()));
+            } else if let Some(first) = exporters.insert(label, name) {
+                errors.push(LinkError::DuplicateExport(label.clone(), first.to_string(), name.clone()));
+            } else {
+                symbols.insert(label.clone(), base + offset, SymbolKind::Label);
+            }
+        }
+        base += object.code.len();
+    }
+    if base > MAX_LITERAL + 1 {
+        errors.push(LinkError::RomOverflow(base));
+    }
+
+    let mut next_var = 16;
+    let mut words = Vec::new();
+    for ((name, object), base) in objects.iter().zip(bases) {
+        for word in &object.code {
+            let word = match word {
+                ObjectWord::Absolute(w) => *w,
+                ObjectWord::Relocatable(offset) => {
+                    if base + offset > MAX_LITERAL {
+                        errors.push(LinkError::AddressOutOfRange(name.clone(), base + offset));
+                    }
+                    (base + offset) as u16
+                },
+                ObjectWord::External(symbol) => match exporters.get(symbol.as_str()) {
+                    Some(_) => *symbols.get(symbol).unwrap() as u16,
+                    None => {
+                        errors.push(LinkError::UndefinedImport(symbol.clone(), name.clone()));
+                        0
+                    },
+                },
+                ObjectWord::Variable(symbol) => match symbols.get(symbol) {
+                    Some(_) if exporters.contains_key(symbol.as_str()) => {
+                        let exporter = exporters[symbol.as_str()].to_string();
+                        errors.push(LinkError::NotImported(symbol.clone(), exporter, name.clone()));
+                        0
+                    },
+                    Some(val) => *val as u16,
+                    None => {
+                        symbols.insert(symbol.clone(), next_var, SymbolKind::Variable);
+                        next_var += 1;
+                        next_var as u16 - 1
+                    },
+                },
+            };
+            words.push(word);
+        }
+    }
+    if errors.is_empty() {
+        Ok(Assembled { words, symbols })
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble_object, link, LinkError, Object, ObjectWord};
+    use crate::{assemble, parse, AsmErrorKind};
+
+    fn object(source: &str) -> Object {
+        assemble_object(&parse("<input>", source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn relocations() {
+        let obj = object(".export MULT\n.import ADD\n(MULT)\n@SCREEN+1\n@MULT+2\n@sum\nD=M\n@LOOP\n(LOOP)\n@ADD");
+        assert_eq!(obj.exports, vec![("MULT".to_string(), 0)]);
+        assert_eq!(obj.code, vec![
+            ObjectWord::Absolute(16385),
+            ObjectWord::Relocatable(2),
+            ObjectWord::Variable("sum".to_string()),
+            ObjectWord::Absolute(0xFC10),
+            ObjectWord::Relocatable(5),
+            ObjectWord::External("ADD".to_string()),
+        ]);
+        assert_eq!(Object::from_text("mult.hobj", &obj.to_text()).unwrap(), obj);
+        assert!(Object::from_text("mult.hobj", "HACKOBJ 1\nrel x").is_err());
+
+        let errors = assemble_object(&parse("<input>", ".export NOPE\n(L)\n@L*2").unwrap()).unwrap_err();
+        assert_eq!(errors[0].kind, AsmErrorKind::UndefinedSymbol("NOPE".to_string()));
+        assert_eq!(errors[1].kind, AsmErrorKind::NotRelocatable("L*2".to_string()));
+        let errors = parse("<input>", "(L)\n.export .loop\n.import .loop").unwrap_err();
+        assert!(errors.iter().all(|e| matches!(e.kind, AsmErrorKind::InvalidDirective(_))) && errors.len() == 2);
+        let errors = assemble_object(&parse("<input>", ".import L\n(L)").unwrap()).unwrap_err();
+        assert_eq!(errors[0].kind, AsmErrorKind::DuplicateSymbol("L".to_string()));
+    }
+
+    #[test]
+    fn link_modules() {
+        let main = ".import INC\n@i\nM=0\n@RET\nD=A\n@R15\nM=D\n@INC\n0;JMP\n(RET)\n@RET\n0;JMP";
+        let lib = ".export INC\n(INC)\n@i\nM=M+1\n@R15\nA=M\n0;JMP";
+        let linked = link(&[("main".to_string(), object(main)), ("lib".to_string(), object(lib))]).unwrap();
+        let whole = assemble(&format!("{}\n{}", main, lib)).unwrap();
+        assert_eq!(linked.words, whole.words);
+        assert_eq!(linked.symbols.get("INC"), Some(&10));
+        assert_eq!(linked.symbols.get("i"), Some(&16));
+
+        // Private labels in different modules don't collide
+        let a = object("(LOOP)\n@LOOP\n0;JMP");
+        assert_eq!(link(&[("a".to_string(), a.clone()), ("b".to_string(), a)]).unwrap().words, vec![0, 0xEA87, 2, 0xEA87]);
+
+        let lib = object(lib);
+        let errors = link(&[("a".to_string(), lib.clone()), ("b".to_string(), lib.clone())]).unwrap_err();
+        assert_eq!(errors, vec![LinkError::DuplicateExport("INC".to_string(), "a".to_string(), "b".to_string())]);
+
+        // A misspelled import is an error rather than a variable, and so is a label used without importing it
+        let misspelled = object(".import INCC\n@INCC\n0;JMP");
+        let errors = link(&[("main".to_string(), misspelled), ("lib".to_string(), lib.clone())]).unwrap_err();
+        assert_eq!(errors, vec![LinkError::UndefinedImport("INCC".to_string(), "main".to_string())]);
+        let unimported = object("@INC\n0;JMP");
+        let errors = link(&[("main".to_string(), unimported), ("lib".to_string(), lib)]).unwrap_err();
+        assert_eq!(errors, vec![LinkError::NotImported("INC".to_string(), "lib".to_string(), "main".to_string())]);
+    }
+}
@@ -0,0 +1,188 @@
+use std::fmt;
+
+use crate::comp::{Comp, Reg};
+use crate::error::AsmError;
+use crate::instr::{Dest, Instr, Program, Statement, Value};
+use crate::local::scope_local_labels;
+use crate::reachable::eliminate_unreachable;
+
+// How many ROM words each kind of optimization removed
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Savings {
+    pub reloads: usize,
+    pub increments: usize,
+    pub stores: usize,
+    pub jumps: usize,
+    pub unreachable: usize,
+    // Labels removed with the unreachable code, which don't take up any ROM themselves
+    pub dead_labels: usize,
+}
+
+impl Savings {
+    pub fn total(&self) -> usize {
+        self.reloads + self.increments + self.stores + self.jumps + self.unreachable
+    }
+}
+
+impl fmt::Display for Savings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "saved {} word(s)", self.total())?;
+        writeln!(f, "  {:>5} redundant A-instructions", self.reloads)?;
+        writeln!(f, "  {:>5} increments cancelled by decrements", self.increments)?;
+        writeln!(f, "  {:>5} stores of a value just loaded", self.stores)?;
+        writeln!(f, "  {:>5} jumps to the next instruction", self.jumps)?;
+        write!(f, "  {:>5} unreachable instructions, under {} label(s)", self.unreachable, self.dead_labels)
+    }
+}
+
+// Remove instructions that can't change what the program does:
+//
+//   @X / M=M+1 / @X / M=M-1     the increment and decrement cancel out
+//   @X / ... / @X               A already holds X
+//   D=M / M=D                   M already holds D
+//   0;JMP / (NEXT)              control reaches NEXT either way, when A holds NEXT. The @NEXT before
+//                               the jump goes too if the code after the label sets A before using it.
+//
+// and then any code that can't be reached from the start of the program.
+// Labels are only resolved afterwards, so every address stays correct. Any label could be jumped to from
+// elsewhere, so nothing is known about A after one, and no pattern spans one.
+pub fn optimize(program: &[Statement]) -> Result<(Vec<Statement>, Savings), Vec<AsmError>> {
+    // Local labels with the same name in different scopes must not be confused for each other
+    let mut program = scope_local_labels(program)?;
+    let mut savings = Savings::default();
+    // Removing one instruction can line up another pattern, so repeat until nothing changes
+    loop {
+        let before = savings.total();
+        program = optimize_pass(&program, &mut savings);
+        program = eliminate_unreachable(&program, &mut savings);
+        if savings.total() == before {
+            return Ok((program, savings));
+        }
+    }
+}
+
+fn optimize_pass(program: &[Statement], savings: &mut Savings) -> Vec<Statement> {
+    let to_m = Dest { a: false, d: false, m: true };
+    let to_d = Dest { a: false, d: true, m: false };
+    let mut out: Vec<Statement> = Vec::new();
+    // The value A is known to hold
+    let mut a: Option<Value> = None;
+    for (idx, statement) in program.iter().enumerate() {
+        let previous = match out.last() {
+            Some(Statement { program: Program::Instr(instr), .. }) => Some(instr),
+            _ => None,
+        };
+        match &statement.program {
+            Program::Instr(Instr::A(value)) => {
+                if a.as_ref() == Some(value) {
+                    savings.reloads += 1;
+                    continue;
+                }
+                a = Some(value.clone());
+            },
+            Program::Instr(Instr::C(dest, comp, jump)) => {
+                if !jump.is_jump() {
+                    let cancels = match (previous, comp) {
+                        (Some(Instr::C(d, Comp::Inc(Reg::M), j)), Comp::Dec(Reg::M))
+                        | (Some(Instr::C(d, Comp::Dec(Reg::M), j)), Comp::Inc(Reg::M)) => {
+                            *d == to_m && *dest == to_m && !j.is_jump()
+                        },
+                        _ => false,
+                    };
+                    if cancels {
+                        out.pop();
+                        savings.increments += 2;
+                        continue;
+                    }
+                    let stored = previous == Some(&Instr::C(to_d.clone(), Comp::Reg(Reg::M), Default::default()));
+                    if stored && *dest == to_m && *comp == Comp::Reg(Reg::D) {
+                        savings.stores += 1;
+                        continue;
+                    }
+                } else if *dest == Dest::new() && jumps_to_next(a.as_ref(), &program[idx + 1..]) {
+                    savings.jumps += 1;
+                    // Without the jump the code after the label would see a different A, unless it sets
+                    // A itself first
+                    if matches!(previous, Some(Instr::A(_))) && sets_a_first(&program[idx + 1..]) {
+                        out.pop();
+                        savings.jumps += 1;
+                    }
+                    continue;
+                }
+                if dest.a {
+                    a = None;
+                }
+            },
+            _ => a = None,
+        }
+        out.push(statement.clone());
+    }
+    out
+}
+
+// Whether the labels straight after a jump include the one A holds
+fn jumps_to_next(a: Option<&Value>, rest: &[Statement]) -> bool {
+    let target = match a {
+        Some(Value::Variable(target)) => target,
+        _ => return false,
+    };
+    rest.iter()
+        .map_while(|s| match &s.program {
+            Program::Label(label) => Some(label),
+            _ => None,
+        })
+        .any(|label| label == target)
+}
+
+// Whether the first instruction after the labels at the start of `rest` is an A-instruction, which
+// replaces A without reading it
+fn sets_a_first(rest: &[Statement]) -> bool {
+    let next = rest.iter().find(|s| !matches!(s.program, Program::Label(_)));
+    matches!(next, Some(Statement { program: Program::Instr(Instr::A(_)), .. }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{optimize, Savings};
+    use crate::{assemble, parse, resolve};
+
+    fn optimized(source: &str) -> (Vec<String>, Savings) {
+        let (program, savings) = optimize(&parse("<input>", source).unwrap()).unwrap();
+        (program.iter().map(|s| s.program.to_string()).collect(), savings)
+    }
+
+    #[test]
+    fn removes_patterns() {
+        let (program, savings) = optimized("@SP\nM=M+1\n@SP\nM=M-1\n@x\nD=M\n@x\nM=D\n@END\n0;JMP\n(END)\n@END\nD;JGT");
+        assert_eq!(program, ["@SP", "@x", "D=M", "(END)", "@END", "D;JGT"]);
+        assert_eq!(savings, Savings { reloads: 2, increments: 2, stores: 1, jumps: 2, ..Default::default() });
+        assert_eq!(savings.total(), 7);
+    }
+
+    #[test]
+    fn jump_to_next_keeps_a() {
+        // M=0 still writes RAM[END], and D=A still loads END
+        let (program, savings) = optimized("@END\n0;JMP\n(END)\nM=0\n@NEXT\n0;JMP\n(NEXT)\nD=A");
+        assert_eq!(program, ["@END", "(END)", "M=0", "@NEXT", "(NEXT)", "D=A"]);
+        assert_eq!(savings, Savings { jumps: 2, ..Default::default() });
+        let (program, _) = optimized("@END\n0;JMP\n(END)\n(HALT)\n@HALT\n0;JMP");
+        assert_eq!(program, ["(END)", "(HALT)", "@HALT", "0;JMP"]);
+    }
+
+    #[test]
+    fn labels_keep_patterns_apart() {
+        let source = "@x\n(A)\n@x\nM=M+1\n(B)\nM=M-1\nAM=M+1\n@x\n@END\nD;JGT\nD=0\n(END)";
+        let (program, savings) = optimized(source);
+        assert_eq!(program, ["@x", "(A)", "@x", "M=M+1", "(B)", "M=M-1", "AM=M+1", "@x", "@END", "D;JGT", "D=0", "(END)"]);
+        assert_eq!(savings.total(), 0);
+    }
+
+    #[test]
+    fn labels_stay_correct() {
+        let source = "@i\nM=M+1\n@i\nM=M-1\n(LOOP)\n@LOOP\n0;JMP";
+        let (program, _) = optimize(&parse("<input>", source).unwrap()).unwrap();
+        let assembled = resolve(&program).unwrap();
+        assert_eq!(assembled.words, [16, 1, 0xEA87]);
+        assert_eq!(assemble(source).unwrap().words.len(), 6);
+    }
+}
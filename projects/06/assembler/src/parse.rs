@@ -0,0 +1,413 @@
+use std::{collections::HashMap, fs, path::{Path, PathBuf}};
+
+use crate::directive::Directive;
+use crate::error::{AsmError, AsmErrorKind};
+use crate::instr::{expand_negative, is_symbol, Program, Statement};
+
+// Deep enough for any reasonable nesting, shallow enough to stop a macro that invokes itself
+const MAX_MACRO_DEPTH: usize = 64;
+
+// Read lines out of the source, ignoring whitespace, and parse them into Statements that keep the comment
+// written after them. Every line is parsed even after a failure so that all of the errors in a file can be
+// reported at once.
+//
+// `.include "file.asm"` splices in the statements of another file, found relative to `file`. Statements
+// keep the file and line they were written on, so diagnostics point into the included file.
+//
+// Macros are defined with `.macro NAME param, ...` up to `.endm`, and invoked by writing `NAME arg, ...` as
+// an instruction. In the body `%param` is replaced by the argument and `%%label` by a local label that is
+// unique to each expansion. Expanded statements are attributed to the line that invoked the macro.
+pub fn parse(file: &str, source: &str) -> Result<Vec<Statement>, Vec<AsmError>> {
+    let mut parser = Parser::new(file, false);
+    parser.parse_source(file, source);
+    if parser.errors.is_empty() {
+        Ok(parser.program)
+    } else {
+        Err(parser.errors)
+    }
+}
+
+// Every line of a file as one Statement, for tools that rewrite source rather than assemble it. Includes
+// aren't followed and macros aren't expanded. Lines that aren't exactly one statement that parses (blank
+// lines, lines with only a comment, includes, macro definitions and invocations, `@-N` and mistakes) are
+// kept as `Program::Source`.
+pub fn parse_verbatim(file: &str, source: &str) -> Vec<Statement> {
+    let mut parser = Parser::new(file, true);
+    parser.parse_source(file, source);
+    parser.program
+}
+
+// Paths that can't be canonicalized (because they don't exist, like "<input>") are compared as written
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+// Where a line came from, for errors and for the Statements it produces
+#[derive(Clone, Copy)]
+struct Origin<'a> {
+    file: &'a str,
+    line: usize,
+    text: &'a str,
+}
+
+struct Parser {
+    // The chain of files currently being parsed, used to catch a file that includes itself
+    includes: Vec<PathBuf>,
+    macros: HashMap<String, Macro>,
+    // Counts every expansion so that each gets its own local labels
+    expansions: usize,
+    program: Vec<Statement>,
+    errors: Vec<AsmError>,
+    // Whether to keep every line as written, for parse_verbatim
+    verbatim: bool,
+}
+
+impl Parser {
+    fn new(file: &str, verbatim: bool) -> Self {
+        Parser {
+            includes: vec![canonical(Path::new(file))],
+            macros: HashMap::new(),
+            expansions: 0,
+            program: Vec::new(),
+            errors: Vec::new(),
+            verbatim,
+        }
+    }
+
+    fn parse_source(&mut self, file: &str, source: &str) {
+        let mut lines = source.lines().enumerate();
+        while let Some((line_idx, line)) = lines.next() {
+            let origin = Origin { file, line: line_idx + 1, text: line };
+            if self.verbatim {
+                self.verbatim_line(origin);
+                continue;
+            }
+            // A macro definition swallows every line up to its `.endm`
+            if let Some(Ok(Directive::Macro(name, params))) = directive(line) {
+                let mut body = Vec::new();
+                let mut terminated = false;
+                for (_, body_line) in lines.by_ref() {
+                    if let Some(Ok(Directive::Endm)) = directive(body_line) {
+                        terminated = true;
+                        break;
+                    }
+                    body.push(body_line.to_string());
+                }
+                if !terminated {
+                    self.error(origin, AsmError::new(AsmErrorKind::UnterminatedMacro(name.clone()), 0, ".macro"));
+                }
+                if self.macros.contains_key(&name) {
+                    self.error(origin, AsmError::new(AsmErrorKind::DuplicateMacro(name.clone()), 0, ".macro"));
+                }
+                self.macros.insert(name, Macro { params, body });
+                continue;
+            }
+            self.parse_line(origin, line, 0);
+        }
+    }
+
+    // Parse `code`, which is either the line in `origin` or a line expanded from a macro invoked there
+    fn parse_line(&mut self, origin: Origin, code: &str, depth: usize) {
+        let (code, comment) = split_comment(code);
+        // Code expanded from a macro isn't where its comments were written
+        let comment = comment.filter(|_| depth == 0);
+        let trimmed_line = code.trim();
+        if trimmed_line.is_empty() {
+            return;
+        };
+        // Columns are only meaningful when the code is what was written on the line
+        let column = if depth == 0 { code.len() - code.trim_start().len() + 1 } else { 1 };
+        let at = |e: AsmError| e.at(origin.file, origin.line, column, if depth == 0 { origin.text } else { trimmed_line });
+        let text = if depth == 0 { origin.text } else { trimmed_line };
+
+        if trimmed_line.starts_with('.') {
+            match Directive::from_string(trimmed_line) {
+                Ok(Directive::Include(name)) => self.include(origin, trimmed_line, &name, at),
+                Ok(Directive::Export(label)) => self.push(origin, Program::Export(label), text, comment),
+                Ok(Directive::Import(label)) => self.push(origin, Program::Import(label), text, comment),
+                Ok(Directive::Data(name)) => self.push(origin, Program::Data(name), text, comment),
+                Ok(Directive::Word(words)) => self.push(origin, Program::Word(words), text, comment),
+                Ok(Directive::Endm) => self.errors.push(at(AsmError::new(AsmErrorKind::UnexpectedEndm, 0, trimmed_line))),
+                Ok(Directive::Macro(name, _)) => {
+                    self.errors.push(at(AsmError::new(AsmErrorKind::NestedMacro(name), 0, trimmed_line)))
+                },
+                Err(e) => self.errors.push(at(e)),
+            }
+            return;
+        }
+
+        let (name, args) = match trimmed_line.split_once(char::is_whitespace) {
+            Some((name, args)) => (name, args.trim()),
+            None => (trimmed_line, ""),
+        };
+        if let Some(m) = self.macros.get(name) {
+            let args: Vec<&str> = args.split(',').map(str::trim).filter(|a| !a.is_empty()).collect();
+            let body: Result<Vec<String>, _> = if depth >= MAX_MACRO_DEPTH {
+                Err(AsmErrorKind::MacroRecursion(name.to_string()))
+            } else if args.len() != m.params.len() {
+                Err(AsmErrorKind::MacroArguments(m.params.len(), args.len()))
+            } else {
+                self.expansions += 1;
+                m.body.iter().map(|line| substitute(name, line, &m.params, &args, self.expansions)).collect()
+            };
+            match body {
+                Ok(body) => {
+                    for line in body {
+                        self.parse_line(origin, &line, depth + 1);
+                    }
+                },
+                Err(kind) => self.errors.push(at(AsmError::new(kind, 0, name))),
+            }
+            return;
+        }
+
+        let parsed = match expand_negative(trimmed_line) {
+            Some(expanded) => expanded,
+            None => Program::from_string(trimmed_line).map(|p| vec![p]),
+        };
+        match parsed {
+            Ok(parsed) => {
+                for p in parsed {
+                    self.push(origin, p, text, comment);
+                }
+            },
+            Err(e) => self.errors.push(at(e)),
+        }
+    }
+
+    // Statements expanded from macros show the expanded code rather than the invocation as their text
+    fn push(&mut self, origin: Origin, program: Program, text: &str, comment: Option<&str>) {
+        self.program.push(Statement {
+            program,
+            file: origin.file.to_string(),
+            line: origin.line,
+            text: text.to_string(),
+            comment: comment.map(str::to_string),
+        });
+    }
+
+    fn verbatim_line(&mut self, origin: Origin) {
+        let (code, comment) = split_comment(origin.text);
+        let code = code.trim();
+        let program = match code.starts_with('.').then(|| Directive::from_string(code)) {
+            Some(Ok(Directive::Export(label))) => Some(Program::Export(label)),
+            Some(Ok(Directive::Import(label))) => Some(Program::Import(label)),
+            Some(Ok(Directive::Data(name))) => Some(Program::Data(name)),
+            Some(Ok(Directive::Word(words))) => Some(Program::Word(words)),
+            Some(_) => None,
+            None if expand_negative(code).is_some() => None,
+            None => Program::from_string(code).ok(),
+        };
+        let program = program.unwrap_or_else(|| Program::Source(code.to_string()));
+        self.push(origin, program, origin.text, comment);
+    }
+
+    fn include(&mut self, origin: Origin, directive: &str, name: &str, at: impl Fn(AsmError) -> AsmError) {
+        let path = Path::new(origin.file).parent().unwrap_or_else(|| Path::new("")).join(name);
+        let error = |kind| at(AsmError::new(kind, directive.find('"').unwrap_or(0), &format!("\"{}\"", name)));
+        if self.includes.contains(&canonical(&path)) {
+            self.errors.push(error(AsmErrorKind::IncludeCycle(path.display().to_string())));
+            return;
+        }
+        match fs::read_to_string(&path) {
+            Ok(included) => {
+                self.includes.push(canonical(&path));
+                self.parse_source(&path.display().to_string(), &included);
+                self.includes.pop();
+            },
+            Err(e) => self.errors.push(error(AsmErrorKind::IncludeFailed(path.display().to_string(), e.kind()))),
+        }
+    }
+
+    fn error(&mut self, origin: Origin, e: AsmError) {
+        let column = origin.text.find(&e.text).unwrap_or(0) + 1;
+        self.errors.push(e.at(origin.file, origin.line, column, origin.text));
+    }
+}
+
+// Split a line into its code and the comment after `//`, if there is one. A `//` inside a string or
+// character literal is part of the code.
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut quote = None;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if line[idx..].starts_with("//") => return (&line[..idx], Some(&line[idx + 2..])),
+            None => {},
+        }
+    }
+    (line, None)
+}
+
+// The directive on a line, if it has one
+fn directive(line: &str) -> Option<Result<Directive, AsmError>> {
+    let code = split_comment(line).0.trim();
+    code.starts_with('.').then(|| Directive::from_string(code))
+}
+
+// Replace `%param` with its argument and `%%label` with a label unique to this expansion
+fn substitute(name: &str, line: &str, params: &[String], args: &[&str], expansion: usize) -> Result<String, AsmErrorKind> {
+    let mut out = String::new();
+    let mut rest = line;
+    while let Some(idx) = rest.find('%') {
+        out += &rest[..idx];
+        rest = &rest[idx + 1..];
+        let local = rest.starts_with('%');
+        if local {
+            rest = &rest[1..];
+        }
+        let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || "_.$:".contains(c))).unwrap_or(rest.len());
+        let ident = &rest[..len];
+        rest = &rest[len..];
+        if local && is_symbol(ident) {
+            // A local label, so that using a macro doesn't end the scope of the local labels around it
+            out += &format!(".{}${}${}", name, ident, expansion);
+        } else if let Some(arg) = params.iter().position(|p| p == ident).map(|i| args[i]) {
+            out += arg;
+        } else {
+            return Err(AsmErrorKind::UnknownMacroParameter(ident.to_string()));
+        }
+    }
+    Ok(out + rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use super::{parse, parse_verbatim};
+    use crate::{resolve, AsmErrorKind, Program};
+
+    // A directory for one test in one run of the tests, removed once the test is done with it
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(test: &str) -> Self {
+            let dir = env::temp_dir().join(format!("assembler-{}-{}", test, process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn includes() {
+        let temp = TempDir::new("includes");
+        let dir = &temp.0;
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("lib/mult.asm"), "(MULT)\n@R2\nM=0\n.include \"ret.asm\"\n").unwrap();
+        fs::write(dir.join("lib/ret.asm"), "@R15\nA=M\n0;JMP").unwrap();
+        fs::write(dir.join("lib/bad.asm"), "D;JXX").unwrap();
+        fs::write(dir.join("lib/loop.asm"), ".include \"../main.asm\"").unwrap();
+        let main = dir.join("main.asm");
+        let main = main.to_str().unwrap();
+
+        let program = parse(main, "@MULT\n0;JMP\n  .include \"lib/mult.asm\" // routines").unwrap();
+        assert_eq!(program.len(), 8);
+        assert_eq!(program[7].file, dir.join("lib/ret.asm").display().to_string());
+        assert_eq!(program[7].line, 3);
+        assert_eq!(resolve(&program).unwrap().symbols.get("MULT"), Some(&2));
+
+        let errors = parse(main, "@R0\n.include \"lib/bad.asm\"").unwrap_err();
+        assert_eq!((errors[0].file.as_str(), errors[0].line), (dir.join("lib/bad.asm").to_str().unwrap(), 1));
+
+        fs::write(main, ".include \"lib/loop.asm\"").unwrap();
+        let errors = parse(main, &fs::read_to_string(main).unwrap()).unwrap_err();
+        assert!(matches!(errors[0].kind, AsmErrorKind::IncludeCycle(_)));
+        assert_eq!(errors[0].file, dir.join("lib/loop.asm").display().to_string());
+
+        let errors = parse(main, ".include \"missing.asm\"\n.include missing.asm\n.incude \"a\"").unwrap_err();
+        assert!(matches!(errors[0].kind, AsmErrorKind::IncludeFailed(_, _)));
+        assert_eq!(errors[0].column, 10);
+        assert!(matches!(errors[1].kind, AsmErrorKind::InvalidDirective(_)));
+        assert_eq!(errors[2].kind, AsmErrorKind::UnknownDirective(".incude".to_string()));
+    }
+
+    #[test]
+    fn comments() {
+        let source = ".macro LOAD x\n  @%x // load\n.endm\n\n@-5 // minus five\n(LOOP)//top\n.string \"//\"\nLOAD 1 // one";
+        let program = parse("<input>", source).unwrap();
+        let comments: Vec<Option<&str>> = program.iter().map(|s| s.comment.as_deref()).collect();
+        assert_eq!(comments, [Some(" minus five"), Some(" minus five"), Some("top"), None, None]);
+        let code: Vec<&str> = program.iter().map(|s| s.code()).collect();
+        assert_eq!(code, ["@-5", "@-5", "(LOOP)", ".string \"//\"", "@1"]);
+
+        // Every line is kept, and only the ones holding a single statement are parsed
+        let lines = parse_verbatim("<input>", source);
+        let programs: Vec<String> = lines.iter().map(|s| s.program.to_string()).collect();
+        assert_eq!(programs, [".macro LOAD x", "@%x", ".endm", "", "@-5", "(LOOP)", ".word 47, 47, 0", "LOAD 1"]);
+        assert_eq!(lines[5].program, Program::Label("LOOP".to_string()));
+        assert!(matches!(lines[4].program, Program::Source(_)));
+        let comments: Vec<Option<&str>> = lines.iter().map(|s| s.comment.as_deref()).collect();
+        assert_eq!(comments, [None, Some(" load"), None, None, Some(" minus five"), Some("top"), None, Some(" one")]);
+    }
+
+    #[test]
+    fn macros() {
+        let source = "\
+.macro PUSH_D
+    @SP
+    A=M
+    M=D
+    @SP
+    M=M+1
+.endm
+.macro PUSH_CONST value // push a constant
+    @%value
+    D=A
+    PUSH_D
+.endm
+.macro WAIT_KEY
+(%%wait)
+    @KBD
+    D=M
+    @%%wait
+    D;JEQ
+.endm
+    PUSH_CONST 7
+    WAIT_KEY
+    WAIT_KEY";
+        let program = parse("<input>", source).unwrap();
+        let text: Vec<String> = program.iter().map(|s| s.program.to_string()).collect();
+        assert_eq!(text[..7], ["@7", "D=A", "@SP", "A=M", "M=D", "@SP", "M=M+1"]);
+        assert_eq!(text[7..], [
+            "(.WAIT_KEY$wait$3)", "@KBD", "D=M", "@.WAIT_KEY$wait$3", "D;JEQ",
+            "(.WAIT_KEY$wait$4)", "@KBD", "D=M", "@.WAIT_KEY$wait$4", "D;JEQ",
+        ]);
+        assert!(program[..7].iter().all(|s| s.line == 20));
+        assert!(resolve(&program).is_ok());
+    }
+
+    #[test]
+    fn macro_errors() {
+        let kinds = |source: &str| -> Vec<(usize, AsmErrorKind)> {
+            parse("<input>", source).unwrap_err().into_iter().map(|e| (e.line, e.kind)).collect()
+        };
+        assert_eq!(kinds(".macro LOAD x\n@%y\n.endm\nLOAD 1\nLOAD 1, 2"), vec![
+            (4, AsmErrorKind::UnknownMacroParameter("y".to_string())),
+            (5, AsmErrorKind::MacroArguments(1, 2)),
+        ]);
+        assert_eq!(kinds(".macro LOOP\nLOOP\n.endm\nLOOP"), vec![(4, AsmErrorKind::MacroRecursion("LOOP".to_string()))]);
+        assert_eq!(kinds(".endm\n.macro A\n.endm\n.macro A\n.endm\n.macro B\nD=0"), vec![
+            (1, AsmErrorKind::UnexpectedEndm),
+            (4, AsmErrorKind::DuplicateMacro("A".to_string())),
+            (6, AsmErrorKind::UnterminatedMacro("B".to_string())),
+        ]);
+        assert_eq!(kinds(".macro BAD\nD=X\n.endm\nBAD")[0], (4, AsmErrorKind::InvalidComp("X".to_string())));
+    }
+}
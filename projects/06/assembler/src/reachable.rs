@@ -0,0 +1,196 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::comp::{Comp, Reg};
+use crate::instr::{Instr, Program, Statement, Value};
+use crate::optimize::Savings;
+
+// A run of instructions that can only be entered at the top, through its labels or by falling into it
+struct Block<'a> {
+    start: usize,
+    instructions: usize,
+    falls_through: bool,
+    // Labels the block loads into A, which it may jump to or pass on as a return address
+    targets: Vec<&'a str>,
+}
+
+impl Block<'_> {
+    fn new(start: usize) -> Self {
+        Block { start, instructions: 0, falls_through: true, targets: Vec::new() }
+    }
+}
+
+// Remove the code that can't be reached from address 0, along with its labels. Every jump goes to the
+// address in A, so a block may continue at any label it loads: either directly, or later through an
+// address it stores (like the return address of a VM function call). Addresses written as numbers, as
+// symbols that aren't labels (like R5 or a variable), or computed from labels with expressions would change
+// meaning when the code before them shrinks, so nothing is removed when:
+//
+// - a jump is made right after loading one of them into A
+// - a label is used in an expression anywhere
+// - a jump goes to a computed A (like `A=M`), and one of them was stored in D or RAM, or a label was stored
+//   with something added to it, or there is `.word` data, any of which might be the address it reads back
+pub(crate) fn eliminate_unreachable(program: &[Statement], savings: &mut Savings) -> Vec<Statement> {
+    let labels: HashSet<&str> = program
+        .iter()
+        .filter_map(|s| match &s.program {
+            Program::Label(label) => Some(label.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    let mut blocks = vec![Block::new(0)];
+    let mut block_of_label: HashMap<&str, usize> = HashMap::new();
+    // What A holds, if it was set since the last label
+    let mut a: Option<&Value> = None;
+    // Whether any jump goes to an address that was computed, and whether any value that isn't a label's
+    // address was stored where such a jump could have read it from
+    let mut computed_jump = false;
+    let mut stores_number = false;
+    for (idx, statement) in program.iter().enumerate() {
+        let block = blocks.last_mut().unwrap();
+        match &statement.program {
+            Program::Label(label) => {
+                if block.instructions > 0 {
+                    blocks.push(Block::new(idx));
+                }
+                block_of_label.insert(label, blocks.len() - 1);
+                a = None;
+            },
+            Program::Instr(Instr::A(value)) => {
+                block.instructions += 1;
+                match value {
+                    Value::Variable(v) if labels.contains(v.as_str()) => block.targets.push(v),
+                    Value::Expr(e) if e.symbols().iter().any(|s| labels.contains(s)) => return program.to_vec(),
+                    _ => {},
+                }
+                a = Some(value);
+            },
+            Program::Instr(Instr::C(dest, comp, jump)) => {
+                block.instructions += 1;
+                if comp.reads_a() && (dest.a || dest.d || dest.m) {
+                    match a {
+                        // Address 0, or a value computed by instructions that have been checked already
+                        Some(Value::Literal(0)) | None => {},
+                        Some(Value::Variable(v)) if labels.contains(v.as_str()) && *comp == Comp::Reg(Reg::A) => {},
+                        Some(_) => stores_number = true,
+                    }
+                }
+                if jump.is_jump() {
+                    match a {
+                        // Address 0 is always reachable anyway
+                        Some(Value::Literal(0)) => {},
+                        None => computed_jump = true,
+                        Some(Value::Variable(v)) if labels.contains(v.as_str()) => {},
+                        Some(Value::Variable(_)) | Some(Value::Literal(_)) | Some(Value::Expr(_)) => {
+                            return program.to_vec()
+                        },
+                    }
+                    block.falls_through = !(jump.lt && jump.eq && jump.gt);
+                    blocks.push(Block::new(idx + 1));
+                }
+                if dest.a {
+                    a = None;
+                }
+            },
+            Program::Word(_) => stores_number = true,
+            Program::Export(_) | Program::Import(_) | Program::Data(_) | Program::Source(_) => {},
+        }
+    }
+    if computed_jump && stores_number {
+        return program.to_vec();
+    }
+
+    // Exported labels can be reached from other modules
+    let mut pending = vec![0];
+    for statement in program {
+        if let Program::Export(label) = &statement.program {
+            pending.extend(block_of_label.get(label.as_str()));
+        }
+    }
+    let mut reachable = vec![false; blocks.len()];
+    while let Some(b) = pending.pop() {
+        if reachable[b] {
+            continue;
+        }
+        reachable[b] = true;
+        if blocks[b].falls_through && b + 1 < blocks.len() {
+            pending.push(b + 1);
+        }
+        pending.extend(blocks[b].targets.iter().filter_map(|label| block_of_label.get(label)));
+    }
+
+    let mut out = Vec::new();
+    for (b, block) in blocks.iter().enumerate() {
+        let end = blocks.get(b + 1).map_or(program.len(), |next| next.start);
+        for statement in &program[block.start..end] {
+            match &statement.program {
+                _ if reachable[b] => out.push(statement.clone()),
+                Program::Label(_) => savings.dead_labels += 1,
+                Program::Instr(_) => savings.unreachable += 1,
+                // Directives don't take up ROM
+                _ => out.push(statement.clone()),
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::eliminate_unreachable;
+    use crate::{parse, Savings};
+
+    fn eliminated(source: &str) -> (Vec<String>, Savings) {
+        let mut savings = Savings::default();
+        let program = eliminate_unreachable(&parse("<input>", source).unwrap(), &mut savings);
+        (program.iter().map(|s| s.program.to_string()).collect(), savings)
+    }
+
+    #[test]
+    fn remove_unreachable_code() {
+        // A VM style call: the return address is stored rather than jumped to
+        let source = "@RET\nD=A\n@F\n0;JMP\n@x\n(RET)\n(END)\n@END\n0;JMP\nD=0\n(G)\nD=1\n(F)\nA=D\nD;JLE\n0;JMP";
+        let (program, savings) = eliminated(source);
+        assert_eq!(program, [
+            "@RET", "D=A", "@F", "0;JMP", "(RET)", "(END)", "@END", "0;JMP", "(F)", "A=D", "D;JLE", "0;JMP",
+        ]);
+        assert_eq!(savings.unreachable, 3);
+        assert_eq!(savings.dead_labels, 1);
+    }
+
+    #[test]
+    fn keep_exports_and_data() {
+        let (program, savings) = eliminated("(MAIN)\n@MAIN\n0;JMP\n.data T\n.word 1\n(LIB)\nD=0\n.export LIB");
+        assert_eq!(program, ["(MAIN)", "@MAIN", "0;JMP", ".data T", ".word 1", "(LIB)", "D=0", ".export LIB"]);
+        assert_eq!(savings.total(), 0);
+    }
+
+    #[test]
+    fn numbers_without_computed_jumps() {
+        let (program, savings) = eliminated("@5\nD=A\n@R15\nM=D\n@END\n0;JMP\nD=0\n(END)\n@END\n0;JMP");
+        assert_eq!(program, ["@5", "D=A", "@R15", "M=D", "@END", "0;JMP", "(END)", "@END", "0;JMP"]);
+        assert_eq!(savings.unreachable, 1);
+    }
+
+    #[test]
+    fn numeric_targets_prevent_removal() {
+        let sources = [
+            "@4\n0;JMP\nD=0\n(X)\nD=1",
+            "@X+1\n0;JMP\nD=0\n(X)\nD=1",
+            "@Y\n0;JMP\nD=0\n(X)\n(Y)\n@X+1",
+            // Symbols that aren't labels are fixed addresses too
+            "@R5\n0;JMP\nD=0\n(X)\nD=1",
+            "@x\nD;JGT\n@END\n0;JMP\nD=0\n(END)\nD=1",
+            // Numbers can reach a jump through RAM or D
+            "@8\nD=A\n@R15\nM=D\n@R15\nA=M\n0;JMP\nD=0\nD=1",
+            "@R5\nD=A\n@END\n0;JMP\nD=0\n(END)\nA=D\n0;JMP",
+            "@END\nD=A+1\n@END\n0;JMP\nD=0\n(END)\nA=D\n0;JMP",
+            ".data T\n.word 7\n@T\nA=M\n0;JMP\nD=0\n(END)\nD=1",
+        ];
+        for source in sources {
+            let (program, savings) = eliminated(source);
+            assert_eq!(program.len(), source.lines().count());
+            assert_eq!(savings.total(), 0);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::{collections::HashMap, fmt};
+
+use crate::error::{AsmError, AsmErrorKind};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum SymbolKind {
+    Predefined,
+    Label,
+    Data,
+    Variable,
+}
+
+impl SymbolKind {
+    pub fn from_string(input: &str) -> Option<Self> {
+        match input {
+            "predefined" => Some(SymbolKind::Predefined),
+            "label" => Some(SymbolKind::Label),
+            "data" => Some(SymbolKind::Data),
+            "variable" => Some(SymbolKind::Variable),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SymbolKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolKind::Predefined => write!(f, "predefined"),
+            SymbolKind::Label => write!(f, "label"),
+            SymbolKind::Data => write!(f, "data"),
+            SymbolKind::Variable => write!(f, "variable"),
+        }
+    }
+}
+
+// Labels hold ROM addresses, while predefined symbols, data blocks and variables hold RAM addresses
+#[derive(Debug, Clone)]
+pub struct SymbolTable(HashMap<String, (usize, SymbolKind)>);
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        let mut symbols = HashMap::new();
+        symbols.insert("R0".to_string(), 0);
+        symbols.insert("R1".to_string(), 1);
+        symbols.insert("R2".to_string(), 2);
+        symbols.insert("R3".to_string(), 3);
+        symbols.insert("R4".to_string(), 4);
+        symbols.insert("R5".to_string(), 5);
+        symbols.insert("R6".to_string(), 6);
+        symbols.insert("R7".to_string(), 7);
+        symbols.insert("R8".to_string(), 8);
+        symbols.insert("R9".to_string(), 9);
+        symbols.insert("R10".to_string(), 10);
+        symbols.insert("R11".to_string(), 11);
+        symbols.insert("R12".to_string(), 12);
+        symbols.insert("R13".to_string(), 13);
+        symbols.insert("R14".to_string(), 14);
+        symbols.insert("R15".to_string(), 15);
+        
+        symbols.insert("SCREEN".to_string(), 16384);
+        symbols.insert("KBD".to_string(), 24576);
+        symbols.insert("SP".to_string(), 0);
+        symbols.insert("LCL".to_string(), 1);
+        symbols.insert("ARG".to_string(), 2);
+        symbols.insert("THIS".to_string(), 3);
+        symbols.insert("THAT".to_string(), 4);
+        SymbolTable(symbols.into_iter().map(|(k, val)| (k, (val, SymbolKind::Predefined))).collect())
+    }
+
+    pub fn insert(&mut self, k: String, val: usize, kind: SymbolKind) -> Option<usize> {
+        self.0.insert(k, (val, kind)).map(|(val, _)| val)
+    }
+
+    pub fn get(&self, k : &str) -> Option<&usize> {
+        self.0.get(k).map(|(val, _)| val)
+    }
+
+    pub fn kind(&self, k: &str) -> Option<SymbolKind> {
+        self.0.get(k).map(|(_, kind)| *kind)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &usize)> {
+        self.0.iter().map(|(k, (val, _))| (k, val))
+    }
+
+    // Every symbol grouped by kind, then ordered by address and name so the output is stable
+    pub fn sorted(&self) -> Vec<(&str, usize, SymbolKind)> {
+        let mut symbols: Vec<_> = self.0.iter().map(|(k, (val, kind))| (k.as_str(), *val, *kind)).collect();
+        symbols.sort_by(|a, b| (a.2, a.1, a.0).cmp(&(b.2, b.1, b.0)));
+        symbols
+    }
+
+    // The `.sym` format: one `kind name address` line per symbol
+    pub fn to_sym(&self) -> String {
+        self.sorted().iter().map(|(name, val, kind)| format!("{} {} {}\n", kind, name, val)).collect()
+    }
+
+    // Read back the `.sym` format, so tools working on `.hack` files can show names. Only the symbols in
+    // the file are defined, predefined ones included.
+    pub fn from_sym(file: &str, source: &str) -> Result<Self, Vec<AsmError>> {
+        let mut symbols = HashMap::new();
+        let mut errors = Vec::new();
+        for (line_idx, line) in source.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let symbol = match fields[..] {
+                [] => continue,
+                [kind, name, val] => SymbolKind::from_string(kind).zip(val.parse().ok()).map(|(kind, val)| (name, val, kind)),
+                _ => None,
+            };
+            match symbol {
+                Some((name, val, kind)) => {
+                    symbols.insert(name.to_string(), (val, kind));
+                },
+                None => errors.push(
+                    AsmError::new(AsmErrorKind::InvalidSymbolLine(line.trim().to_string()), 0, line.trim())
+                        .at(file, line_idx + 1, line.len() - line.trim_start().len() + 1, line),
+                ),
+            }
+        }
+        if errors.is_empty() {
+            Ok(SymbolTable(symbols))
+        } else {
+            Err(errors)
+        }
+    }
+
+    // Symbols can only contain letters, digits and `_.$:`, so names never need escaping in JSON
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self.sorted().iter().map(|(name, val, kind)| {
+            format!("    {{\"name\": \"{}\", \"kind\": \"{}\", \"address\": {}}}", name, kind, val)
+        }).collect();
+        format!("{{\n  \"symbols\": [\n{}\n  ]\n}}\n", entries.join(",\n"))
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SymbolKind, SymbolTable};
+    use crate::AsmErrorKind;
+
+    #[test]
+    fn sym_and_json() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("LOOP".to_string(), 4, SymbolKind::Label);
+        symbols.insert("i".to_string(), 16, SymbolKind::Variable);
+        let sym = symbols.to_sym();
+        assert!(sym.starts_with("predefined R0 0\npredefined SP 0\npredefined LCL 1\n"));
+        assert!(sym.ends_with("predefined KBD 24576\nlabel LOOP 4\nvariable i 16\n"));
+        let json = symbols.to_json();
+        assert!(json.contains("    {\"name\": \"LOOP\", \"kind\": \"label\", \"address\": 4},\n"));
+        assert!(json.ends_with("    {\"name\": \"i\", \"kind\": \"variable\", \"address\": 16}\n  ]\n}\n"));
+
+        let read = SymbolTable::from_sym("t.sym", &sym).unwrap();
+        assert_eq!(read.sorted(), symbols.sorted());
+        let errors = SymbolTable::from_sym("t.sym", "label LOOP 4\n\n  label END\nconstant X 1\n").unwrap_err();
+        assert_eq!(errors.iter().map(|e| (e.line, e.column)).collect::<Vec<_>>(), vec![(3, 3), (4, 1)]);
+        assert_eq!(errors[0].kind, AsmErrorKind::InvalidSymbolLine("label END".to_string()));
+    }
+}